@@ -0,0 +1,87 @@
+//! `xdot adopt`: move existing files into a package, then link them back.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{builtin_vars, dot_prefix, scan, Context, Options};
+
+pub fn run(context: &Context, package: &OsStr, paths: &[PathBuf], options: &Options) -> Result<()> {
+    if paths.is_empty() {
+        bail!("No paths specified");
+    }
+
+    let package_path = context.package_path(package);
+
+    for path in paths {
+        let target = std::path::absolute(path)
+            .with_context(|| format!("Invalid path {}", path.display()))?;
+
+        let metadata = target
+            .symlink_metadata()
+            .with_context(|| format!("Unable to adopt {}", target.display()))?;
+
+        if metadata.is_symlink() {
+            bail!("{} is a symlink, it can't be adopted", target.display());
+        }
+
+        let destination = match designating_var(context, &target) {
+            Some((var, relative)) => package_path.join(format!("@{var}")).join(relative),
+            None => match target.strip_prefix(context.layout_root()) {
+                Ok(relative) => package_path.join(relative),
+                Err(_) => bail!(
+                    "Unable to adopt {}: it is outside of {}",
+                    target.display(),
+                    context.layout_root().display()
+                ),
+            },
+        };
+
+        // Hidden files are stored with a `dot-` prefix, where it is translated.
+        let destination = if dot_prefix::is_enabled(&package_path, options) {
+            destination
+                .strip_prefix(&package_path)?
+                .iter()
+                .fold(package_path.to_path_buf(), |path, name| {
+                    path.join(dot_prefix::source_name(name))
+                })
+        } else {
+            destination
+        };
+
+        if destination.symlink_metadata().is_ok() {
+            bail!(
+                "Unable to adopt {}: {} already exists",
+                target.display(),
+                destination.display()
+            );
+        }
+
+        scan::adopt(&target, &destination, options)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the variable designating the closest directory containing `target`, if any, along with
+/// the path of `target` relative to it.
+fn designating_var<'a>(context: &Context, target: &'a Path) -> Option<(String, &'a Path)> {
+    let names = ["HOME"]
+        .into_iter()
+        .chain(builtin_vars().map(|(name, _)| *name))
+        .chain(context.var_mappings.keys().map(String::as_str))
+        .chain(context.var_defaults.keys().map(String::as_str));
+
+    names
+        .filter_map(|name| {
+            let directory = context.resolve_var(OsStr::new(name))?;
+            let relative = target.strip_prefix(&directory).ok()?;
+
+            (!relative.as_os_str().is_empty()).then_some((name, relative))
+        })
+        .min_by_key(|(_, relative)| relative.components().count())
+        .map(|(name, relative)| (name.to_owned(), relative))
+}
@@ -0,0 +1,502 @@
+//! `--backup`: move files out of the way before they are replaced or removed, `xdot backups` lists
+//! them (or prunes old ones) and `xdot restore` puts them back.
+//!
+//! Each run backs up into its own directory, named after the time it started, where files keep
+//! their path relative to `/`.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{parents, print_removal, shell_quote, term, walk_files, Context, Options};
+
+/// Returns the directory holding backups, `dir` (from `--backup=DIR`) unless empty.
+fn root(context: &Context, dir: Option<&Path>) -> Result<PathBuf> {
+    match dir.filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => std::path::absolute(dir)
+            .with_context(|| format!("Invalid backup directory {}", dir.display())),
+        None => context
+            .resolve_var(OsStr::new("XDG_STATE_HOME"))
+            .map(|state| state.join("xdot/backups"))
+            .context("Unable to resolve `XDG_STATE_HOME`"),
+    }
+}
+
+/// Returns the directory the current run backs up into.
+pub fn session(context: &Context, dir: &Path) -> Result<PathBuf> {
+    Ok(root(context, Some(dir))?.join(timestamp()))
+}
+
+/// Removes the file `link`, moving it into the backup of the run with `--backup`. With
+/// `--output sh`, prints the corresponding commands instead.
+pub fn remove(link: &Path, options: &Options) -> Result<()> {
+    if options.backup.is_some() {
+        return back_up(link, options);
+    }
+
+    if options.output_sh {
+        print_removal(link, options);
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    std::fs::remove_file(link).with_context(|| format!("Unable to remove {}", link.display()))
+}
+
+/// Moves the file `link` into the backup of the run. With `--output sh`, prints the corresponding
+/// commands instead.
+pub fn back_up(link: &Path, options: &Options) -> Result<()> {
+    let Some(backup) = destination(link, "mv", options)? else {
+        return Ok(());
+    };
+
+    move_file(link, &backup)
+}
+
+/// Copies the file `link` into the backup of the run, leaving it in place to be replaced at once.
+/// With `--output sh`, prints the corresponding commands instead.
+pub fn copy(link: &Path, options: &Options) -> Result<()> {
+    let Some(backup) = destination(link, "cp -p", options)? else {
+        return Ok(());
+    };
+
+    if let Some(parent) = backup.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+
+    std::fs::copy(link, &backup)
+        .with_context(|| format!("Unable to copy {} to {}", link.display(), backup.display()))?;
+
+    Ok(())
+}
+
+/// Returns where `link` is backed up by the run, unless nothing is to be done: with `--dry-run`,
+/// or with `--output sh`, where `command` is printed instead.
+fn destination(link: &Path, command: &str, options: &Options) -> Result<Option<PathBuf>> {
+    let session = options
+        .backup_session
+        .as_ref()
+        .context("No backup directory for this run")?;
+
+    let backup = session.join(link.strip_prefix("/").unwrap_or(link));
+
+    if options.output_sh {
+        if let Some(parent) = backup.parent() {
+            println!("mkdir -p {}", shell_quote(parent.as_os_str()));
+        }
+
+        println!(
+            "{command} {} {}",
+            shell_quote(link.as_os_str()),
+            shell_quote(backup.as_os_str())
+        );
+
+        return Ok(None);
+    }
+
+    if options.verbosity > 0 {
+        println!("Backing up {} to {}", link.display(), backup.display());
+    }
+
+    if options.dry_run {
+        return Ok(None);
+    }
+
+    if backup.symlink_metadata().is_ok() {
+        bail!(
+            "Unable to back up {}: {} already exists",
+            link.display(),
+            backup.display()
+        );
+    }
+
+    Ok(Some(backup))
+}
+
+/// Returns the backups in `root`, oldest first.
+fn sessions(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut sessions = match root.read_dir() {
+        Ok(entries) => entries
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(error) => {
+            return Err(error).with_context(|| format!("Unable to read {}", root.display()))
+        }
+    };
+
+    sessions.sort();
+
+    Ok(sessions)
+}
+
+/// `xdot backups`: lists the backups, along with their files with `-v`.
+pub fn list(context: &Context, options: &Options) -> Result<()> {
+    let root = root(context, options.backup.as_deref())?;
+    let sessions = sessions(&root)?;
+
+    if sessions.is_empty() {
+        println!("No backups in {}.", root.display());
+
+        return Ok(());
+    }
+
+    for session in sessions {
+        let mut files = Vec::new();
+
+        walk_files(
+            context,
+            &session,
+            Path::new("/"),
+            options,
+            &mut |_, link| {
+                files.push(link.to_owned());
+
+                Ok(())
+            },
+        )?;
+
+        println!(
+            "{} ({} file(s))",
+            session.file_name().unwrap_or_default().to_string_lossy(),
+            files.len()
+        );
+
+        if options.verbosity > 0 {
+            for file in files {
+                println!("  {}", file.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `xdot restore ID`: moves the files of a backup back into place, replacing the symlinks found
+/// there.
+pub fn restore(context: &Context, id: &OsStr, options: &Options) -> Result<()> {
+    let session = root(context, options.backup.as_deref())?.join(id);
+
+    if !session.is_dir() {
+        bail!("Unknown backup `{}`", id.to_string_lossy());
+    }
+
+    let mut files = Vec::new();
+
+    walk_files(
+        context,
+        &session,
+        Path::new("/"),
+        options,
+        &mut |backup, link| {
+            files.push((backup.to_owned(), link.to_owned()));
+
+            Ok(())
+        },
+    )?;
+
+    // Nothing is restored unless everything can be.
+    let occupied = files
+        .iter()
+        .filter(|(_, link)| {
+            link.symlink_metadata()
+                .is_ok_and(|metadata| !metadata.is_symlink())
+        })
+        .map(|(_, link)| format!("\n  {}", link.display()))
+        .collect::<String>();
+
+    if !occupied.is_empty() {
+        bail!(
+            "Unable to restore `{}`, these files exist:{occupied}",
+            id.to_string_lossy()
+        );
+    }
+
+    for (backup, link) in &files {
+        if options.output_sh {
+            println!(
+                "mv {} {}",
+                shell_quote(backup.as_os_str()),
+                shell_quote(link.as_os_str())
+            );
+        } else {
+            println!(
+                "{}",
+                term::paint(&format!("Restoring {}", link.display()), term::Color::Green)
+            );
+        }
+
+        if options.dry_run {
+            continue;
+        }
+
+        if link.symlink_metadata().is_ok() {
+            std::fs::remove_file(link)
+                .with_context(|| format!("Unable to remove {}", link.display()))?;
+        }
+
+        move_file(backup, link)?;
+    }
+
+    if !options.dry_run {
+        std::fs::remove_dir_all(&session)
+            .with_context(|| format!("Unable to remove {}", session.display()))?;
+    }
+
+    Ok(())
+}
+
+/// `xdot backups --prune`: removes the backups beyond the `--keep` most recent ones, and those
+/// older than `--max-age` days, then forgets the created directories which no longer exist.
+///
+/// Only directories named by [`timestamp`] are considered, anything else in the backup directory
+/// is left alone.
+pub fn prune(context: &Context, options: &Options) -> Result<()> {
+    if options.keep.is_none() && options.max_age.is_none() {
+        bail!("`--prune` needs `--keep N` or `--max-age DAYS`");
+    }
+
+    let root = root(context, options.backup.as_deref())?;
+
+    // Timestamps sort chronologically.
+    let oldest_kept = options.max_age.map(|days| {
+        let days = libc::time_t::try_from(days).unwrap_or(libc::time_t::MAX / 86_400);
+
+        timestamp_of(unsafe { libc::time(std::ptr::null_mut()) }.saturating_sub(days * 86_400))
+    });
+
+    let sessions = sessions(&root)?
+        .into_iter()
+        .filter(|session| session.file_name().is_some_and(is_timestamp))
+        .collect::<Vec<_>>();
+
+    let mut reclaimed = 0;
+    let mut removed = 0;
+
+    for (index, session) in sessions.iter().rev().enumerate() {
+        let id = session.file_name().unwrap_or_default();
+
+        let is_expired = oldest_kept
+            .as_ref()
+            .is_some_and(|oldest| id.as_encoded_bytes() < oldest.as_bytes());
+
+        if options.keep.is_none_or(|keep| index < keep) && !is_expired {
+            continue;
+        }
+
+        let size = size(session)?;
+
+        println!(
+            "{}",
+            term::paint(
+                &format!(
+                    "Removing backup {} ({})",
+                    id.to_string_lossy(),
+                    format_size(size)
+                ),
+                term::Color::Yellow
+            )
+        );
+
+        if !options.dry_run {
+            std::fs::remove_dir_all(session)
+                .with_context(|| format!("Unable to remove {}", session.display()))?;
+        }
+
+        reclaimed += size;
+        removed += 1;
+    }
+
+    parents::vacuum(options)?;
+
+    println!(
+        "{} {removed} backup(s), {}.",
+        if options.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        },
+        format_size(reclaimed)
+    );
+
+    Ok(())
+}
+
+/// Returns the size of the files under `path`, as they take in their directory entries.
+fn size(path: &Path) -> Result<u64> {
+    let metadata = path
+        .symlink_metadata()
+        .with_context(|| format!("Unable to read metadata of {}", path.display()))?;
+
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut size = 0;
+
+    for entry in path
+        .read_dir()
+        .with_context(|| format!("Unable to descend into {}", path.display()))?
+    {
+        size += self::size(&entry?.path())?;
+    }
+
+    Ok(size)
+}
+
+/// Returns `size` in bytes in the largest unit it has at least one of, e.g. `1.5 MiB`.
+fn format_size(size: u64) -> String {
+    let mut value = size as f64;
+
+    for unit in ["bytes", "KiB", "MiB", "GiB"] {
+        if value < 1024.0 || unit == "GiB" {
+            return if unit == "bytes" {
+                format!("{size} bytes")
+            } else {
+                format!("{value:.1} {unit}")
+            };
+        }
+
+        value /= 1024.0;
+    }
+
+    unreachable!()
+}
+
+/// Moves the file or directory `from` to `to`, creating the parents of `to`, across filesystems
+/// if needed.
+pub fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+
+    match std::fs::rename(from, to) {
+        Err(error) if error.kind() == std::io::ErrorKind::CrossesDevices => {
+            // `from` is only removed once fully copied, a partial copy is removed instead.
+            if let Err(error) = copy_tree(from, to) {
+                let _ = if to
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.is_dir())
+                {
+                    std::fs::remove_dir_all(to)
+                } else {
+                    std::fs::remove_file(to)
+                };
+
+                return Err(error);
+            }
+
+            if from.symlink_metadata()?.is_dir() {
+                std::fs::remove_dir_all(from)
+            } else {
+                std::fs::remove_file(from)
+            }
+            .with_context(|| format!("Unable to remove {}", from.display()))
+        }
+        result => {
+            result.with_context(|| format!("Unable to move {} to {}", from.display(), to.display()))
+        }
+    }
+}
+
+/// Copies `from` to `to` as is: recursing into directories, keeping symlinks and permissions.
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    let metadata = from
+        .symlink_metadata()
+        .with_context(|| format!("Unable to read metadata of {}", from.display()))?;
+
+    if metadata.is_symlink() {
+        let target = from
+            .read_link()
+            .with_context(|| format!("Unable to read {}", from.display()))?;
+
+        std::os::unix::fs::symlink(target, to)
+            .with_context(|| format!("Unable to create {}", to.display()))?;
+    } else if metadata.is_dir() {
+        std::fs::create_dir(to).with_context(|| format!("Unable to create {}", to.display()))?;
+
+        for entry in from
+            .read_dir()
+            .with_context(|| format!("Unable to descend into {}", from.display()))?
+        {
+            let entry = entry?;
+
+            copy_tree(&entry.path(), &to.join(entry.file_name()))?;
+        }
+
+        std::fs::set_permissions(to, metadata.permissions())
+            .with_context(|| format!("Unable to set permissions of {}", to.display()))?;
+    } else {
+        std::fs::copy(from, to)
+            .with_context(|| format!("Unable to copy {} to {}", from.display(), to.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the local time, e.g. `2024-01-31T12-00-00`, colons being troublesome in paths.
+fn timestamp() -> String {
+    timestamp_of(unsafe { libc::time(std::ptr::null_mut()) })
+}
+
+/// Returns the local time `time` in the format of [`timestamp`].
+fn timestamp_of(time: libc::time_t) -> String {
+    let mut tm = std::mem::MaybeUninit::<libc::tm>::uninit();
+
+    let tm = unsafe {
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            return time.to_string();
+        }
+
+        tm.assume_init()
+    };
+
+    format!(
+        "{}-{:02}-{:02}T{:02}-{:02}-{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+/// Returns whether `name` is a [`timestamp`], that is the name of a backup made by xdot.
+fn is_timestamp(name: &OsStr) -> bool {
+    let name = name.as_encoded_bytes();
+
+    name.len() == "2024-01-31T12-00-00".len()
+        && name.iter().enumerate().all(|(index, byte)| match index {
+            4 | 7 | 13 | 16 => *byte == b'-',
+            10 => *byte == b'T',
+            _ => byte.is_ascii_digit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_backup_names() {
+        assert!(is_timestamp(OsStr::new(&timestamp())));
+        assert!(is_timestamp(OsStr::new(&timestamp_of(0))));
+        assert!(!is_timestamp(OsStr::new("2024-01-31T12:00:00")));
+        assert!(!is_timestamp(OsStr::new("mine")));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+    }
+}
@@ -0,0 +1,142 @@
+//! `xdot bench`, for development: time runs over a generated package, so that changes to walking
+//! packages and applying links can be measured without a large repository at hand. Hidden from
+//! `--help`.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{Options, DEFAULT_MAX_DESCEND_DEPTH, DEFAULT_MAX_FILES_PER_PACKAGE};
+
+const DEFAULT_FILES: usize = 1000;
+
+const DEFAULT_DEPTH: usize = 3;
+
+/// Subdirectories per directory of the generated package.
+const FAN_OUT: usize = 10;
+
+/// Files per directory at the bottom of the generated package.
+const FILES_PER_DIRECTORY: usize = 10;
+
+const PACKAGE: &str = "synthetic";
+
+/// The runs timed, by name: each one starts from the state the previous one left.
+const PHASES: &[(&str, &[&str])] = &[
+    ("plan", &["--dry-run"]),
+    ("apply", &[]),
+    ("up to date", &[]),
+    ("unlink", &["--unlink"]),
+];
+
+pub fn run(options: &Options) -> Result<()> {
+    let files = options.files.unwrap_or(DEFAULT_FILES);
+    let depth = options.depth.unwrap_or(DEFAULT_DEPTH);
+
+    let root = std::env::temp_dir().join(format!("xdot-bench-{}", std::process::id()));
+
+    std::fs::create_dir(&root).with_context(|| format!("Unable to create {}", root.display()))?;
+
+    println!(
+        "Benchmarking {files} files, {depth} directories deep, in {}",
+        root.display()
+    );
+
+    let result = generate(&root.join("packages").join(PACKAGE), files, depth)
+        .and_then(|()| configure(&root.join("home"), files, depth))
+        .and_then(|()| time_phases(&root));
+
+    std::fs::remove_dir_all(&root)
+        .with_context(|| format!("Unable to remove {}", root.display()))?;
+
+    result
+}
+
+/// Creates `files` files in `package`, spread over directories `depth` levels deep.
+fn generate(package: &Path, files: usize, depth: usize) -> Result<()> {
+    for index in 0..files {
+        let mut directory = package.join("@HOME");
+        let mut rest = index / FILES_PER_DIRECTORY;
+
+        for _ in 0..depth {
+            directory.push(format!("d{}", rest % FAN_OUT));
+            rest /= FAN_OUT;
+        }
+
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Unable to create {}", directory.display()))?;
+
+        let path = directory.join(format!("f{index}"));
+
+        std::fs::write(&path, index.to_string())
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes the `config.toml` of `home`, with limits which the generated package fits in.
+fn configure(home: &Path, files: usize, depth: usize) -> Result<()> {
+    let directory = home.join(".config/xdot");
+    let path = directory.join("config.toml");
+
+    std::fs::create_dir_all(&directory)
+        .with_context(|| format!("Unable to create {}", directory.display()))?;
+
+    std::fs::write(
+        &path,
+        format!(
+            "max_files_per_package = {}\nmax_descend_depth = {}\n",
+            files.max(DEFAULT_MAX_FILES_PER_PACKAGE),
+            (depth + 2).max(DEFAULT_MAX_DESCEND_DEPTH)
+        ),
+    )
+    .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Runs xdot over the generated package for every phase, printing how long each one took.
+fn time_phases(root: &Path) -> Result<()> {
+    let program = std::env::current_exe().context("Unable to find the xdot executable")?;
+    let home = root.join("home");
+
+    for (name, args) in PHASES {
+        let mut command = Command::new(&program);
+
+        // Every file is linked on its own, rather than folded into a single link.
+        command
+            .arg("--dir")
+            .arg(root.join("packages"))
+            .args(["--no-folding", "--quick"])
+            .args(*args)
+            .arg(PACKAGE)
+            .env("HOME", &home)
+            .env_remove("XDOT_DIR")
+            .stdout(Stdio::null());
+
+        // The configuration of the machine running the benchmark is left out.
+        for (var, _) in std::env::vars_os() {
+            if var.to_string_lossy().starts_with("XDG_") {
+                command.env_remove(var);
+            }
+        }
+
+        let start = Instant::now();
+
+        let status = command
+            .status()
+            .with_context(|| format!("Unable to run {}", program.display()))?;
+
+        let elapsed = start.elapsed();
+
+        if !status.success() {
+            bail!("The `{name}` run failed: {status}");
+        }
+
+        println!("{name:<12}{:>10.1}ms", elapsed.as_secs_f64() * 1000.0);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! `xdot blame`: show which package a target comes from, along with the last commit touching its
+//! source file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{is_linked, walk_files, Context, Options, PackageSpec};
+
+pub fn run(context: &Context, target: &Path, options: &Options) -> Result<()> {
+    let target = std::path::absolute(target)
+        .with_context(|| format!("Invalid target {}", target.display()))?;
+
+    let mut owners = Vec::<(String, PathBuf)>::new();
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            if !target.starts_with(&link) {
+                continue;
+            }
+
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                if link == target {
+                    owners.push((package.to_string_lossy().into_owned(), original.to_owned()));
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    if owners.is_empty() {
+        bail!("{} isn't a target of any package", target.display());
+    }
+
+    for (index, (package, original)) in owners.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        println!(
+            "Package:     {package}{}",
+            if is_linked(original, &target) {
+                ""
+            } else {
+                " (not linked)"
+            }
+        );
+        println!("Source:      {}", original.display());
+        println!("Last change: {}", last_change(context, original)?);
+    }
+
+    Ok(())
+}
+
+/// Describes the last commit touching `path`.
+fn last_change(context: &Context, path: &Path) -> Result<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&*context.packages_root)
+        .args([
+            "log",
+            "-1",
+            "--date=short",
+            "--format=%h %an, %ad: %s",
+            "--",
+        ])
+        .arg(path)
+        .output()
+        .context("Unable to execute git command")?;
+
+    if !output.status.success() {
+        return Ok(String::from("unknown (not in a git repository)"));
+    }
+
+    let change = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    Ok(if change.is_empty() {
+        String::from("not committed")
+    } else {
+        change
+    })
+}
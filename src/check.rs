@@ -0,0 +1,93 @@
+//! `xdot check`: fail if any package file isn't linked, for monitoring.
+
+use anyhow::{bail, Result};
+
+use crate::{executables, walk_files, Context, LinkState, Options, PackageSpec};
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let mut drifted = 0;
+    let mut not_executable = 0;
+
+    for package in context.packages(package_spec)?.iter() {
+        let mut counts = [0usize; 4];
+        let mut package_not_executable = 0;
+
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                let state = LinkState::of(original, link, options);
+
+                let index = match state {
+                    LinkState::Linked | LinkState::Copied => 0,
+                    LinkState::Missing => 1,
+                    LinkState::Conflict | LinkState::CopyModified => 2,
+                    LinkState::WrongTarget => 3,
+                };
+
+                counts[index] += 1;
+
+                if !matches!(state, LinkState::Linked | LinkState::Copied) && !options.summary {
+                    println!("{}: {}", state.as_str(), link.display());
+                }
+
+                if state == LinkState::Linked
+                    && executables::is_meant_executable(original)
+                    && !executables::is_executable(original)
+                {
+                    package_not_executable += 1;
+
+                    if !options.summary {
+                        println!("not executable: {}", link.display());
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+
+        if options.summary {
+            println!(
+                "{}",
+                summary_line(&package.to_string_lossy(), counts, package_not_executable)
+            );
+        }
+
+        drifted += counts[1..].iter().sum::<usize>();
+        not_executable += package_not_executable;
+    }
+
+    if drifted > 0 {
+        bail!("{drifted} file(s) aren't linked");
+    }
+
+    if not_executable > 0 {
+        bail!(
+            "{not_executable} file(s) of `@XDG_BIN_HOME` aren't executable, relink them to fix it"
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the `--summary` line of `package`, in a stable format for monitoring tools to parse.
+fn summary_line(package: &str, counts: [usize; 4], not_executable: usize) -> String {
+    let [ok, missing, conflicting, stale] = counts;
+
+    format!(
+        "{package} ok={ok} missing={missing} conflicting={conflicting} stale={stale} \
+        nonexecutable={not_executable}"
+    )
+}
+
+// Tools parse these lines, pairs may only be added at their end.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_line_format() {
+        assert_eq!(
+            summary_line("nvim", [42, 3, 0, 1], 2),
+            "nvim ok=42 missing=3 conflicting=0 stale=1 nonexecutable=2"
+        );
+    }
+}
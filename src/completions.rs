@@ -0,0 +1,274 @@
+//! `xdot completions`: print (or install) shell completion scripts.
+
+use std::{ffi::OsStr, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+
+use crate::{Context, Options};
+
+// Kept in sync with the parser of `main.rs` by the tests below.
+const COMMANDS: &[&str] = &[
+    "scan",
+    "diff",
+    "githook",
+    "graph",
+    "report",
+    "freeze",
+    "thaw",
+    "deploy",
+    "export",
+    "targets",
+    "status",
+    "check",
+    "prune",
+    "restow",
+    "orphans",
+    "backups",
+    "restore",
+    "retry",
+    "doctor",
+    "verify-repo",
+    "list",
+    "search",
+    "blame",
+    "explain",
+    "state",
+    "new",
+    "adopt",
+    "completions",
+    "bench",
+];
+
+const OPTIONS: &[&str] = &[
+    "--all",
+    "--hidden-packages",
+    "--dir",
+    "--target",
+    "--layout",
+    "--env",
+    "--unlink",
+    "--interactive",
+    "--yes",
+    "--no-input",
+    "--dry-run",
+    "--quick",
+    "--keep-broken",
+    "--no-folding",
+    "--force",
+    "--backup",
+    "--include-vcs",
+    "--follow-dir-symlinks",
+    "--template",
+    "--relative",
+    "--hardlink",
+    "--keep-going",
+    "--retries",
+    "--allow-foreign-dirs",
+    "--dangling-sources",
+    "--output",
+    "--conflicts-report",
+    "--snapshot",
+    "--notify",
+    "--umask",
+    "--only",
+    "--exclude",
+    "--filter",
+    "--host",
+    "--all-hosts",
+    "--remote-dir",
+    "--install",
+    "--summary",
+    "--long",
+    "--mermaid",
+    "--depth",
+    "--files",
+    "--prune",
+    "--keep",
+    "--max-age",
+    "--vcs",
+    "--security",
+    "--verbose",
+    "--help",
+    "--version",
+];
+
+#[derive(Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(shell: &str) -> Result<Self> {
+        match shell {
+            "bash" => Ok(Self::Bash),
+            "zsh" => Ok(Self::Zsh),
+            "fish" => Ok(Self::Fish),
+            _ => Err(anyhow!(
+                "Unknown shell `{shell}`, expected `bash`, `zsh` or `fish`"
+            )),
+        }
+    }
+}
+
+impl Shell {
+    /// Guesses the user's shell from `$SHELL`.
+    fn detect() -> Result<Self> {
+        let shell = std::env::var_os("SHELL").context("$SHELL is not set, specify a shell")?;
+
+        PathBuf::from(&shell)
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| {
+                format!(
+                    "Unable to detect the shell from {}",
+                    shell.to_string_lossy()
+                )
+            })
+    }
+
+    fn script(self) -> String {
+        let commands = COMMANDS.join(" ");
+        let options = OPTIONS.join(" ");
+
+        match self {
+            Self::Bash => format!(
+                r#"# bash completion for xdot, generated by `xdot completions bash`.
+_xdot() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" words="{options}" dir
+
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        words="$words {commands}"
+    fi
+
+    words="$words $(xdot list 2>/dev/null)"
+
+    COMPREPLY=($(compgen -W "$words" -- "$cur"))
+}}
+
+complete -F _xdot xdot
+"#
+            ),
+            Self::Zsh => format!(
+                r#"#compdef xdot
+# zsh completion for xdot, generated by `xdot completions zsh`.
+local -a words
+words=({options} ${{(f)"$(xdot list 2>/dev/null)"}})
+
+if (( CURRENT == 2 )); then
+    words+=({commands})
+fi
+
+compadd -a words
+"#
+            ),
+            Self::Fish => format!(
+                r#"# fish completion for xdot, generated by `xdot completions fish`.
+complete -c xdot -f
+complete -c xdot -n __fish_use_subcommand -a '{commands}'
+complete -c xdot -a '(xdot list 2>/dev/null)'
+complete -c xdot -a '{options}'
+"#
+            ),
+        }
+    }
+
+    /// Returns where the user's completion scripts are conventionally installed.
+    fn install_path(self, context: &Context) -> Result<PathBuf> {
+        let var = |name: &str| {
+            context
+                .resolve_var(OsStr::new(name))
+                .ok_or_else(|| anyhow!("Unable to resolve `{name}`"))
+        };
+
+        Ok(match self {
+            Self::Bash => var("XDG_DATA_HOME")?.join("bash-completion/completions/xdot"),
+            Self::Zsh => var("XDG_DATA_HOME")?.join("zsh/site-functions/_xdot"),
+            Self::Fish => var("XDG_CONFIG_HOME")?.join("fish/completions/xdot.fish"),
+        })
+    }
+}
+
+pub fn run(context: &Context, shell: Option<Shell>, options: &Options) -> Result<()> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None if options.install => Shell::detect()?,
+        None => bail!("Missing shell, expected `bash`, `zsh` or `fish`"),
+    };
+
+    if !options.install {
+        print!("{}", shell.script());
+
+        return Ok(());
+    }
+
+    let path = shell.install_path(context)?;
+
+    println!("Writing completions to {}", path.display());
+
+    if !options.dry_run {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+
+        std::fs::write(&path, shell.script())
+            .with_context(|| format!("Unable to write {}", path.display()))?;
+    }
+
+    if let Shell::Zsh = shell {
+        println!(
+            "Add `fpath+=({})` to your .zshrc before `compinit` if it isn't already there",
+            path.parent().unwrap_or(&path).display()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use regex::Regex;
+
+    use super::*;
+
+    const PARSER: &str = include_str!("main.rs");
+
+    fn parsed(pattern: &str) -> BTreeSet<String> {
+        Regex::new(pattern)
+            .unwrap()
+            .captures_iter(PARSER)
+            .flat_map(|captures| {
+                Regex::new(r#""([a-z-]+)""#)
+                    .unwrap()
+                    .captures_iter(captures.get(1).unwrap().as_str())
+                    .map(|name| name[1].to_owned())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn commands_match_the_parser() {
+        let completed = COMMANDS.iter().map(|&command| command.to_owned()).collect();
+
+        assert_eq!(parsed(r#"(?m)^\s*("[a-z-]+") => Some\(Self::"#), completed);
+    }
+
+    #[test]
+    fn options_match_the_parser() {
+        let completed = OPTIONS
+            .iter()
+            .map(|option| option.trim_start_matches("--").to_owned())
+            .collect();
+
+        assert_eq!(parsed(r"Arg::Long\(([^)]*)\)"), completed);
+    }
+}
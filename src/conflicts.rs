@@ -0,0 +1,209 @@
+//! `--conflicts-report FILE`: describe every conflict of a run as JSON, so that provisioning
+//! pipelines can surface them.
+
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Context as _, Result};
+use serde::Serialize;
+
+/// Bumped on incompatible changes to the report, fields may be added without bumping it.
+const SCHEMA_VERSION: u32 = 1;
+
+/// A target which couldn't be linked because something else exists there.
+pub struct Conflict {
+    pub package: String,
+    pub original: PathBuf,
+    pub link: PathBuf,
+}
+
+#[derive(Serialize)]
+struct Report {
+    schema_version: u32,
+    conflicts: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    package: String,
+    source: PathBuf,
+    target: PathBuf,
+    /// `file`, `directory`, `symlink`, `broken-symlink` or `other`.
+    kind: &'static str,
+    size: Option<u64>,
+    /// Seconds since the Unix epoch.
+    modified: Option<u64>,
+    symlink_target: Option<PathBuf>,
+    suggestions: Vec<String>,
+}
+
+impl Entry {
+    fn new(conflict: &Conflict) -> Self {
+        let Conflict {
+            package,
+            original,
+            link,
+        } = conflict;
+
+        let metadata = link.symlink_metadata().ok();
+        let symlink_target = link.read_link().ok();
+
+        let kind = match &metadata {
+            Some(metadata) if metadata.is_symlink() && !link.exists() => "broken-symlink",
+            Some(metadata) if metadata.is_symlink() => "symlink",
+            Some(metadata) if metadata.is_dir() => "directory",
+            Some(metadata) if metadata.is_file() => "file",
+            _ => "other",
+        };
+
+        let mut suggestions = match kind {
+            "broken-symlink" => vec![
+                format!("Remove the broken symlink: rm {}", link.display()),
+                String::from("Run xdot without `--keep-broken` to replace it"),
+            ],
+            "symlink" => vec![
+                format!("Remove the symlink: rm {}", link.display()),
+                String::from("Unlink the package which owns it, if any"),
+            ],
+            _ => vec![
+                format!(
+                    "Adopt it, replacing the packaged version: mv {} {}",
+                    link.display(),
+                    original.display()
+                ),
+                format!("Remove it: rm -r {}", link.display()),
+            ],
+        };
+
+        if kind == "file" {
+            suggestions.push(String::from("Run xdot with `--force` to replace it"));
+        }
+
+        Self {
+            package: package.clone(),
+            source: original.clone(),
+            target: link.clone(),
+            kind,
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+            symlink_target,
+            suggestions,
+        }
+    }
+}
+
+pub fn write(path: &Path, conflicts: &[Conflict]) -> Result<()> {
+    std::fs::write(path, to_json(conflicts)? + "\n")
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+fn to_json(conflicts: &[Conflict]) -> Result<String> {
+    let report = Report {
+        schema_version: SCHEMA_VERSION,
+        conflicts: conflicts.iter().map(Entry::new).collect(),
+    };
+
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+// Tools parse the report, changing what these pin requires bumping `SCHEMA_VERSION`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(dir: &Path, conflicts: &[Conflict]) -> serde_json::Value {
+        let json = to_json(conflicts).unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xdot-{name}-{}", std::process::id()));
+
+        std::fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn starts_with_schema_version() {
+        assert_eq!(SCHEMA_VERSION, 1);
+        assert!(to_json(&[])
+            .unwrap()
+            .starts_with("{\n  \"schema_version\": 1,\n  \"conflicts\": []"));
+    }
+
+    #[test]
+    fn file_entry() {
+        let dir = temp_dir("conflicts-file");
+        let link = dir.join("zshrc");
+
+        std::fs::write(&link, "abc").unwrap();
+
+        let report = report(
+            &dir,
+            &[Conflict {
+                package: String::from("zsh"),
+                original: PathBuf::from("/home/me/.xdot/zsh/@HOME/.zshrc"),
+                link: link.clone(),
+            }],
+        );
+
+        let entry = &report["conflicts"][0];
+        let mut keys: Vec<_> = entry.as_object().unwrap().keys().cloned().collect();
+
+        keys.sort();
+
+        assert_eq!(
+            keys,
+            [
+                "kind",
+                "modified",
+                "package",
+                "size",
+                "source",
+                "suggestions",
+                "symlink_target",
+                "target"
+            ]
+        );
+        assert_eq!(entry["package"], "zsh");
+        assert_eq!(entry["source"], "/home/me/.xdot/zsh/@HOME/.zshrc");
+        assert_eq!(entry["target"], link.to_str().unwrap());
+        assert_eq!(entry["kind"], "file");
+        assert_eq!(entry["size"], 3);
+        assert!(entry["modified"].is_u64());
+        assert!(entry["symlink_target"].is_null());
+        assert!(entry["suggestions"].as_array().unwrap().len() > 1);
+    }
+
+    #[test]
+    fn symlink_kinds() {
+        let dir = temp_dir("conflicts-symlinks");
+
+        std::os::unix::fs::symlink(&dir, dir.join("symlink")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), dir.join("broken")).unwrap();
+
+        let conflicts = ["symlink", "broken"].map(|name| Conflict {
+            package: String::from("zsh"),
+            original: PathBuf::from("/home/me/.xdot/zsh/@HOME/.zshrc"),
+            link: dir.join(name),
+        });
+
+        let report = report(&dir, &conflicts);
+
+        assert_eq!(report["conflicts"][0]["kind"], "symlink");
+        assert_eq!(
+            report["conflicts"][0]["symlink_target"],
+            dir.to_str().unwrap()
+        );
+        assert_eq!(report["conflicts"][1]["kind"], "broken-symlink");
+    }
+}
@@ -0,0 +1,385 @@
+//! `xdot deploy`: copy packages to a remote host with `rsync` then link them there with `ssh`, or
+//! with `xdot export script` when xdot isn't installed there.
+//!
+//! Hosts can be described in `hosts.toml`, at the root of the packages directory:
+//!
+//! ```toml
+//! [profiles.desktop]
+//! packages = ["zsh", "nvim", "sway"]
+//!
+//! [laptop]
+//! address = "me@laptop.local" # Defaults to `hostname`, then to the entry name.
+//! hostname = "laptop"         # Operated on in place from the machine of this name.
+//! profile = "desktop"         # Takes the packages of the profile.
+//! packages = ["zsh", "nvim"]  # Defaults to those of `profile`, or every package.
+//! packages_root = "dotfiles"  # Defaults to `.xdot`, relative to the remote `$HOME`.
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    io::Write as _,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+
+use crate::{export, link, read_toml, shell_quote, state, status, Context, Options, PackageSpec};
+
+/// Where packages are copied on remote hosts, relative to their `$HOME`.
+const DEFAULT_REMOTE_DIR: &str = ".xdot";
+
+/// Files at the root of the packages directory which are copied along with the packages, as they
+/// affect how they are linked.
+const ROOT_FILES: &[&str] = &["vars.toml", "xdot.toml"];
+
+/// `hosts.toml`: hosts by name, along with the profiles they share.
+#[derive(Deserialize)]
+struct Hosts {
+    /// Lists of packages, by name.
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(flatten)]
+    hosts: BTreeMap<String, Host>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    packages: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Host {
+    address: Option<String>,
+    /// What `hostname` prints on the host, to tell whether it is this machine.
+    hostname: Option<String>,
+    /// Takes the packages of this profile, unless `packages` is given.
+    profile: Option<String>,
+    packages: Option<Vec<String>>,
+    packages_root: Option<String>,
+}
+
+/// A host to operate on, resolved from `hosts.toml` and the command line.
+struct Target {
+    name: OsString,
+    address: OsString,
+    remote_dir: OsString,
+    package_spec: PackageSpec,
+    /// Whether the host is this machine, operated on in place rather than over `ssh`.
+    is_local: bool,
+}
+
+impl Hosts {
+    /// Resolves the host `name`, falling back to it as an address when it isn't in `hosts.toml`.
+    /// `package_spec` replaces its packages, unless empty.
+    fn target(
+        hosts: Option<&Self>,
+        name: &OsStr,
+        package_spec: PackageSpec,
+        options: &Options,
+    ) -> Result<Target> {
+        let entry = hosts.and_then(|hosts| Some((hosts, hosts.hosts.get(name.to_str()?)?)));
+
+        let Some((hosts, host)) = entry else {
+            return Ok(Target {
+                name: name.to_owned(),
+                address: name.to_owned(),
+                remote_dir: options
+                    .remote_dir
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_REMOTE_DIR.into()),
+                package_spec,
+                is_local: false,
+            });
+        };
+
+        let packages = match (&host.packages, &host.profile) {
+            (Some(packages), _) => Some(packages),
+            (None, Some(profile)) => match hosts.profiles.get(profile) {
+                Some(profile) => Some(&profile.packages),
+                None => bail!(
+                    "Unknown profile `{profile}` for `{}` in hosts.toml",
+                    name.to_string_lossy()
+                ),
+            },
+            (None, None) => None,
+        };
+
+        Ok(Target {
+            name: name.to_owned(),
+            address: host
+                .address
+                .as_deref()
+                .or(host.hostname.as_deref())
+                .map_or_else(|| name.to_owned(), OsString::from),
+            remote_dir: options
+                .remote_dir
+                .clone()
+                .or_else(|| host.packages_root.as_deref().map(OsString::from))
+                .unwrap_or_else(|| DEFAULT_REMOTE_DIR.into()),
+            package_spec: match (package_spec, packages) {
+                (PackageSpec::None, Some(packages)) => PackageSpec::List(
+                    packages
+                        .iter()
+                        .map(|package| OsStr::new(package).into())
+                        .collect(),
+                ),
+                (PackageSpec::None, None) => PackageSpec::All,
+                (package_spec, _) => package_spec,
+            },
+            is_local: host
+                .hostname
+                .as_ref()
+                .is_some_and(|hostname| *hostname == state::host_name()),
+        })
+    }
+}
+
+/// Reads `hosts.toml`, if it exists.
+fn hosts(context: &Context) -> Result<Option<Hosts>> {
+    read_toml(&context.packages_root.join("hosts.toml"))
+}
+
+pub fn run(
+    context: &Context,
+    host: Option<&OsStr>,
+    package_spec: PackageSpec,
+    options: &Options,
+) -> Result<()> {
+    let hosts = hosts(context)?;
+
+    if options.all_hosts {
+        let Some(hosts) = &hosts else {
+            bail!("`--all-hosts` requires a hosts.toml file in the packages directory");
+        };
+
+        for name in hosts.hosts.keys() {
+            let target = Hosts::target(Some(hosts), OsStr::new(name), PackageSpec::None, options)?;
+
+            deploy(context, target, options)?;
+        }
+
+        return Ok(());
+    }
+
+    let Some(host) = host else {
+        bail!("No host specified");
+    };
+
+    deploy(
+        context,
+        Hosts::target(hosts.as_ref(), host, package_spec, options)?,
+        options,
+    )
+}
+
+/// `xdot status --host HOST`: runs `xdot status` on the host, or here if it is this machine.
+pub fn status(
+    context: &Context,
+    host: &OsStr,
+    package_spec: PackageSpec,
+    options: &Options,
+) -> Result<()> {
+    let hosts = hosts(context)?;
+    let target = Hosts::target(hosts.as_ref(), host, package_spec, options)?;
+
+    if target.is_local {
+        return status::run(context, target.package_spec, options);
+    }
+
+    let mut remote = format!("xdot status --dir {}", remote_path(&target.remote_dir));
+
+    for _ in 0..options.verbosity {
+        remote.push_str(" --verbose");
+    }
+
+    if let PackageSpec::List(packages) = &target.package_spec {
+        remote.push_str(" --");
+
+        for package in packages {
+            remote.push(' ');
+            remote.push_str(&shell_quote(package));
+        }
+    }
+
+    let status = Command::new("ssh")
+        .arg(&target.address)
+        .arg(remote)
+        .status()
+        .context("Unable to execute ssh")?;
+
+    if !status.success() {
+        bail!("Remote xdot failed: {status}");
+    }
+
+    Ok(())
+}
+
+/// Copies the packages to the host, then links them there. On this machine, they are linked in
+/// place.
+fn deploy(context: &Context, target: Target, options: &Options) -> Result<()> {
+    if target.is_local {
+        println!(
+            "{} is this machine, linking packages in place",
+            target.name.to_string_lossy()
+        );
+
+        return link(context, target.package_spec, options);
+    }
+
+    let host = &*target.address;
+    let remote_dir = &*target.remote_dir;
+    let packages = context.packages(target.package_spec)?;
+
+    println!(
+        "Copying packages to {}:{}",
+        host.to_string_lossy(),
+        remote_dir.to_string_lossy()
+    );
+
+    let mut destination = host.to_owned();
+    destination.push(":");
+    destination.push(remote_dir);
+    destination.push("/");
+
+    let mut rsync = Command::new("rsync");
+
+    // Remote paths aren't split by the remote shell.
+    rsync.args(["--archive", "--delete", "--protect-args"]);
+
+    if options.dry_run {
+        rsync.arg("--dry-run");
+    }
+
+    if options.verbosity > 0 {
+        rsync.arg("--verbose");
+    }
+
+    let status = rsync
+        .args(
+            packages
+                .iter()
+                .map(|package| context.package_path(package).into_path_buf()),
+        )
+        .args(
+            ROOT_FILES
+                .iter()
+                .map(|file| context.packages_root.join(file))
+                .filter(|path| path.is_file()),
+        )
+        .arg(destination)
+        .status()
+        .context("Unable to execute rsync")?;
+
+    if !status.success() {
+        bail!("`rsync` failed: {status}");
+    }
+
+    if !has_xdot(host)? {
+        return run_script(context, host, remote_dir, &packages, options);
+    }
+
+    println!("Linking packages on {}", host.to_string_lossy());
+
+    let mut remote = format!("xdot --dir {}", remote_path(remote_dir));
+
+    if options.dry_run {
+        remote.push_str(" --dry-run");
+    }
+
+    for _ in 0..options.verbosity {
+        remote.push_str(" --verbose");
+    }
+
+    remote.push_str(" --");
+
+    for package in packages.iter() {
+        remote.push(' ');
+        remote.push_str(&shell_quote(package));
+    }
+
+    let status = Command::new("ssh")
+        .arg(host)
+        .arg(remote)
+        .status()
+        .context("Unable to execute ssh")?;
+
+    if !status.success() {
+        bail!("Remote xdot failed: {status}");
+    }
+
+    Ok(())
+}
+
+/// Returns the shell expression of the absolute path of `remote_dir` on remote hosts, so that
+/// links don't depend on the directory commands run from.
+fn remote_path(remote_dir: &OsStr) -> String {
+    if remote_dir.to_string_lossy().starts_with('/') {
+        shell_quote(remote_dir)
+    } else {
+        format!("\"$HOME\"/{}", shell_quote(remote_dir))
+    }
+}
+
+/// Returns whether `xdot` can be found in the `$PATH` of `host`.
+fn has_xdot(host: &OsStr) -> Result<bool> {
+    let status = Command::new("ssh")
+        .arg(host)
+        .arg("command -v xdot >/dev/null")
+        .status()
+        .context("Unable to execute ssh")?;
+
+    // ssh exits with 255 when it fails itself.
+    if status.code() == Some(255) {
+        bail!("`ssh` failed: {status}");
+    }
+
+    Ok(status.success())
+}
+
+/// Links the packages on `host` by running the script of `xdot export script` there, with the
+/// packages root set to `remote_dir`.
+fn run_script(
+    context: &Context,
+    host: &OsStr,
+    remote_dir: &OsStr,
+    packages: &[Box<OsStr>],
+    options: &Options,
+) -> Result<()> {
+    println!(
+        "xdot isn't installed on {}, linking packages with `xdot export script`",
+        host.to_string_lossy()
+    );
+
+    let remote = format!("XDOT_DIR={} sh", remote_path(remote_dir));
+
+    let script = export::script_of(context, PackageSpec::List(packages.to_vec()), options)?;
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let mut ssh = Command::new("ssh")
+        .arg(host)
+        .arg(remote)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Unable to execute ssh")?;
+
+    if let Some(mut stdin) = ssh.stdin.take() {
+        stdin
+            .write_all(script.as_bytes())
+            .context("Unable to send the script to ssh")?;
+    }
+
+    let status = ssh.wait().context("Unable to execute ssh")?;
+
+    if !status.success() {
+        bail!("Remote script failed: {status}");
+    }
+
+    Ok(())
+}
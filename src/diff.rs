@@ -0,0 +1,65 @@
+//! `xdot diff --vcs`: show uncommitted changes to the package files that are currently linked.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{is_linked, skip, walk_files, Context, Options, PackageSpec, SkipReason};
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    if !options.vcs {
+        bail!("`xdot diff` requires `--vcs`");
+    }
+
+    let mut linked = Vec::<PathBuf>::new();
+
+    for package in context.packages(package_spec)?.iter() {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                if is_linked(original, link) {
+                    linked.push(original.to_owned());
+                } else {
+                    skip(link, SkipReason::NotLinked, options);
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    if linked.is_empty() {
+        println!("No linked files.");
+
+        return Ok(());
+    }
+
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(&*context.packages_root)
+        .args(["diff", "HEAD", "--"])
+        .args(&linked)
+        .status()
+        .context("Unable to execute git command")?;
+
+    if !status.success() {
+        bail!("`git diff` failed: {status}");
+    }
+
+    Ok(())
+}
+
+/// Prints how the file `existing`, which conflicts with the package file `original`, differs from
+/// it with `diff -u`, returns whether they have the same content. Nothing is printed if `diff`
+/// isn't available.
+pub fn show_conflict(original: &Path, existing: &Path) -> bool {
+    let status = Command::new("diff")
+        .arg("-u")
+        .arg(original)
+        .arg(existing)
+        .status();
+
+    status.is_ok_and(|status| status.success())
+}
@@ -0,0 +1,220 @@
+//! `xdot doctor`: diagnose the setup and broken links, `--security` checks that sensitive files
+//! aren't exposed.
+
+use std::{
+    ffi::OsStr,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    is_linked, modes,
+    prune::{self, BrokenLink},
+    shell_quote, walk_files, Context, Options, PackageSpec,
+};
+
+/// Directories, relative to `$HOME`, whose content is sensitive.
+const SENSITIVE_DIRECTORIES: &[&str] = &[".ssh", ".gnupg"];
+
+/// Substrings of file names which are likely sensitive.
+const SENSITIVE_NAMES: &[&str] = &["token", "key"];
+
+pub fn run(context: &Context, options: &Options) -> Result<()> {
+    println!(
+        "Packages root: {} ({})",
+        context.packages_root.display(),
+        context.packages_root_origin
+    );
+
+    let mut issues = check_links(context, options)? + check_modes(context, options)?;
+
+    if options.security {
+        for package in context.packages(PackageSpec::All)?.iter() {
+            // Invalid packages are reported above.
+            let Ok(entries) = context.package_entries(&context.package_path(package), options)
+            else {
+                continue;
+            };
+
+            for (original, link) in entries {
+                walk_files(context, &original, &link, options, &mut |original, link| {
+                    if is_linked(original, link) && is_sensitive(context, link) {
+                        issues += check_exposure(context, original, link)?;
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+    }
+
+    if issues > 0 {
+        bail!("{issues} issue(s) found");
+    }
+
+    println!("No issues found.");
+
+    Ok(())
+}
+
+/// Reports symlinks into the packages root which are broken, returns the number of issues.
+fn check_links(context: &Context, options: &Options) -> Result<usize> {
+    let broken_links = prune::broken_links(context, options)?;
+
+    for BrokenLink {
+        link,
+        target,
+        package,
+    } in &broken_links
+    {
+        if !context.package_path(package).is_dir() {
+            println!(
+                "{} points into `{}`, which no longer exists, run `rm {}`",
+                link.display(),
+                package.to_string_lossy(),
+                shell_quote(link.as_os_str())
+            );
+        } else if let Some(original) = moved_to(context, package, link, options)? {
+            println!(
+                "{} points to {}, which moved to {}, run `rm {} && xdot {}`",
+                link.display(),
+                target.display(),
+                original.display(),
+                shell_quote(link.as_os_str()),
+                shell_quote(package)
+            );
+        } else {
+            println!(
+                "{} points to {}, which no longer exists, run `xdot prune {}`",
+                link.display(),
+                target.display(),
+                shell_quote(package)
+            );
+        }
+    }
+
+    Ok(broken_links.len())
+}
+
+/// Reports package files whose mode differs from their `modes.toml`, and packages which can't be
+/// walked, e.g. because of an invalid `links.toml`. Returns the number of issues.
+fn check_modes(context: &Context, options: &Options) -> Result<usize> {
+    let mut issues = 0;
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        let entries = match context.package_entries(&context.package_path(package), options) {
+            Ok(entries) => entries,
+            Err(error) => {
+                println!("`{}`: {error:#}", package.to_string_lossy());
+
+                issues += 1;
+
+                continue;
+            }
+        };
+
+        for (original, _) in entries {
+            for drift in modes::drifted(context, &original, options) {
+                println!(
+                    "{} has mode {:03o} rather than {:03o} ({}), run `xdot {}`",
+                    drift.path.display(),
+                    drift.mode,
+                    drift.expected,
+                    modes::FILE,
+                    shell_quote(package)
+                );
+
+                issues += 1;
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Returns the file of `package` which `link` should now point to, if any.
+fn moved_to(
+    context: &Context,
+    package: &OsStr,
+    link: &Path,
+    options: &Options,
+) -> Result<Option<PathBuf>> {
+    for (original, entry_link) in
+        context.package_entries(&context.package_path(package), options)?
+    {
+        if let Ok(relative) = link.strip_prefix(&entry_link) {
+            let original = if relative.as_os_str().is_empty() {
+                original
+            } else {
+                original.join(relative)
+            };
+
+            if original.symlink_metadata().is_ok() {
+                return Ok(Some(original));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn is_sensitive(context: &Context, link: &Path) -> bool {
+    let name = link
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+
+    SENSITIVE_DIRECTORIES
+        .iter()
+        .any(|directory| link.starts_with(context.home.join(directory)))
+        || SENSITIVE_NAMES.iter().any(|pattern| name.contains(pattern))
+}
+
+/// Reports whether the sensitive package file `original` can be read, or listed, by other users,
+/// returns the number of issues.
+fn check_exposure(context: &Context, original: &Path, link: &Path) -> Result<usize> {
+    let mut issues = 0;
+
+    let mode = original.metadata()?.permissions().mode();
+
+    if mode & 0o044 != 0 {
+        println!(
+            "{} is readable by {} (through {}), run `chmod go-rwx {}`",
+            original.display(),
+            if mode & 0o004 != 0 {
+                "everyone"
+            } else {
+                "its group"
+            },
+            link.display(),
+            original.display()
+        );
+
+        issues += 1;
+    }
+
+    // Symlinks don't protect their targets, the directories leading to them do.
+    for directory in original.ancestors().skip(1) {
+        if !directory.starts_with(&context.packages_root) {
+            break;
+        }
+
+        if directory.metadata()?.permissions().mode() & 0o004 != 0 {
+            println!(
+                "{} is world-readable, exposing the name of {}, run `chmod o-rwx {}`",
+                directory.display(),
+                original.display(),
+                directory.display()
+            );
+
+            issues += 1;
+
+            break;
+        }
+    }
+
+    Ok(issues)
+}
@@ -0,0 +1,73 @@
+//! `dot-` prefixes: in the packages enabling them with `dot_prefix` in `xdot.toml`, package files
+//! named e.g. `dot-bashrc` are linked as `.bashrc`, so that they aren't hidden in the packages
+//! repository.
+
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::Options;
+
+const PREFIX: &[u8] = b"dot-";
+
+/// `dot_prefix` in `xdot.toml`: `true` for every package, or a list of packages.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Setting {
+    All(bool),
+    Packages(Vec<String>),
+}
+
+/// Returns the package directories the translation is enabled in by `setting`, under
+/// `packages_root`.
+pub fn packages(packages_root: &Path, setting: &Setting) -> Vec<PathBuf> {
+    match setting {
+        Setting::All(false) => Vec::new(),
+        Setting::All(true) => vec![packages_root.to_owned()],
+        Setting::Packages(packages) => packages
+            .iter()
+            .map(|package| packages_root.join(package))
+            .collect(),
+    }
+}
+
+/// Returns whether the translation applies to the package file (or directory) `original`.
+pub fn is_enabled(original: &Path, options: &Options) -> bool {
+    options
+        .dot_prefixed
+        .iter()
+        .any(|package| original.starts_with(package))
+}
+
+/// Returns the name the package file `original` is linked as.
+pub fn link_name<'a>(original: &'a Path, options: &Options) -> Cow<'a, OsStr> {
+    let name = original.file_name().unwrap_or_default();
+
+    match name.as_bytes().strip_prefix(PREFIX) {
+        Some(rest) if !rest.is_empty() && is_enabled(original, options) => {
+            let mut translated = OsString::from(".");
+            translated.push(OsStr::from_bytes(rest));
+
+            Cow::Owned(translated)
+        }
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// Returns the name a file named `name` is stored as in a package translating `dot-` prefixes.
+pub fn source_name(name: &OsStr) -> Cow<'_, OsStr> {
+    match name.as_bytes().strip_prefix(b".") {
+        Some(rest) if !rest.is_empty() && rest != b"." => {
+            let mut translated = OsString::from("dot-");
+            translated.push(OsStr::from_bytes(rest));
+
+            Cow::Owned(translated)
+        }
+        _ => Cow::Borrowed(name),
+    }
+}
@@ -0,0 +1,64 @@
+//! `@XDG_BIN_HOME`: package files linked there are executables, they are made executable when
+//! linked, `xdot check` and `xdot verify-repo` report those which aren't.
+
+use std::{ffi::OsStr, os::unix::fs::PermissionsExt, path::Path};
+
+use anyhow::{Context as _, Result};
+
+use crate::{shell_quote, term, Options};
+
+/// The `@` directory holding executables.
+const BIN_DIRECTORY: &str = "@XDG_BIN_HOME";
+
+/// Returns whether the package file `original` is meant to be executable.
+pub fn is_meant_executable(original: &Path) -> bool {
+    original
+        .ancestors()
+        .skip(1)
+        .any(|ancestor| ancestor.file_name() == Some(OsStr::new(BIN_DIRECTORY)))
+}
+
+/// Returns whether `path` is a file with an executable bit, following symlinks.
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Makes the package file `original` executable by whoever can read it, if it is meant to be.
+pub fn ensure(original: &Path, options: &Options) -> Result<()> {
+    if !is_meant_executable(original) || is_executable(original) {
+        return Ok(());
+    }
+
+    let Ok(metadata) = original.metadata() else {
+        return Ok(());
+    };
+
+    if !metadata.is_file() {
+        return Ok(());
+    }
+
+    if options.output_sh {
+        println!("chmod +x {}", shell_quote(original.as_os_str()));
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &format!("Making {} executable", original.display()),
+                term::Color::Yellow
+            )
+        );
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let mode = metadata.permissions().mode();
+
+    std::fs::set_permissions(
+        original,
+        std::fs::Permissions::from_mode(mode | (mode & 0o444) >> 2),
+    )
+    .with_context(|| format!("Unable to make {} executable", original.display()))
+}
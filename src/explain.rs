@@ -0,0 +1,279 @@
+//! `xdot explain`: show how a single path, a target or a package file, is handled, from the
+//! package owning it to what is currently on disk.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{
+    builtin_vars, dangling_reason, executables, folded_directory, is_copied, is_linked, links,
+    strip_at_sign_prefix, walk_files, wsl, Context, LinkStrategy, Options, PackageSpec,
+};
+
+/// A package file along with the package and top-level entry it comes from.
+struct Owner {
+    package: String,
+    original: PathBuf,
+    link: PathBuf,
+    /// The top-level package entry containing `original`.
+    entry: PathBuf,
+}
+
+pub fn run(context: &Context, path: &Path, options: &Options) -> Result<()> {
+    let path =
+        std::path::absolute(path).with_context(|| format!("Invalid path {}", path.display()))?;
+
+    let mut owners = Vec::new();
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        let entries = match context.package_entries(&context.package_path(package), options) {
+            Ok(entries) => entries,
+            Err(error) => {
+                eprintln!(
+                    "Warning: skipping `{}`: {error:#}",
+                    package.to_string_lossy()
+                );
+
+                continue;
+            }
+        };
+
+        for (entry, link) in entries {
+            if !path.starts_with(&link) && !path.starts_with(&entry) {
+                continue;
+            }
+
+            walk_files(context, &entry, &link, options, &mut |original, link| {
+                if link == path || original == path {
+                    owners.push(Owner {
+                        package: package.to_string_lossy().into_owned(),
+                        original: original.to_owned(),
+                        link: link.to_owned(),
+                        entry: entry.clone(),
+                    });
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    if owners.is_empty() {
+        bail!(
+            "{} isn't a target nor a file of any package",
+            path.display()
+        );
+    }
+
+    for (index, owner) in owners.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+
+        explain(context, owner, options);
+    }
+
+    Ok(())
+}
+
+fn explain(context: &Context, owner: &Owner, options: &Options) {
+    let Owner {
+        package,
+        original,
+        link,
+        entry,
+    } = owner;
+
+    println!("Package:     {package}");
+    println!("Source:      {}", original.display());
+    println!("Mapping:     {}", mapping(context, package, entry));
+    println!("Target:      {}", link.display());
+
+    let dangling = original
+        .symlink_metadata()
+        .ok()
+        .and_then(|metadata| dangling_reason(original, metadata.file_type()));
+
+    let handling = match dangling {
+        Some(reason) => format!("skipped ({reason}), see `--dangling-sources`"),
+        None => handling(entry, original, options),
+    };
+
+    println!("Handling:    {handling}");
+
+    if let Some(filter) = &options.filter {
+        println!(
+            "Filter:      {}",
+            if filter.matches(&crate::filter::Operation {
+                action: "link",
+                source: original,
+                target: link,
+            }) {
+                "matches"
+            } else {
+                "doesn't match, skipped"
+            }
+        );
+    }
+
+    println!("State:       {}", state(original, link));
+
+    // Symlinked directories among the ancestors of the target, e.g. folded into a package.
+    for ancestor in link.ancestors().skip(1) {
+        let Ok(target) = ancestor.read_link() else {
+            continue;
+        };
+
+        println!(
+            "Through:     {} is a symlink to {}{}",
+            ancestor.display(),
+            target.display(),
+            if folded_directory(ancestor, &target, options).is_some() {
+                " (folded into a package, unfolded when another package links into it)"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// Describes how the top-level package entry `entry` is mapped to its target.
+fn mapping(context: &Context, package: &str, entry: &Path) -> String {
+    if let Some(target) = context
+        .package_config_of(entry)
+        .and_then(|config| config.links.get(entry).cloned())
+    {
+        return format!(
+            "`{target}` in {}",
+            context
+                .package_path(OsStr::new(package))
+                .join(links::FILE)
+                .display()
+        );
+    }
+
+    let var = entry
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(strip_at_sign_prefix);
+
+    let Some(name) = var else {
+        return format!(
+            "relative to {} (layout `{}`{})",
+            context.layout_root().display(),
+            context.layout.as_str(),
+            if context.target.is_some() {
+                ", `--target`"
+            } else {
+                ""
+            }
+        );
+    };
+
+    let resolved = context.resolve_var(name).map_or_else(
+        || String::from("unresolved"),
+        |path| path.display().to_string(),
+    );
+
+    format!(
+        "@{} = {resolved} ({}{})",
+        name.to_string_lossy(),
+        var_origin(context, name),
+        if context.target.is_some() {
+            ", moved into `--target`"
+        } else {
+            ""
+        }
+    )
+}
+
+/// Describes where the value of the variable `name` comes from, in the order
+/// [`Context::resolve_var`] looks.
+fn var_origin(context: &Context, name: &OsStr) -> &'static str {
+    let is_builtin = || builtin_vars().any(|(var, _)| OsStr::new(var) == name);
+
+    if context.env.contains_key(name) {
+        "set with `--env`"
+    } else if std::env::var_os(name).is_some() {
+        "from the environment"
+    } else if name
+        .to_str()
+        .is_some_and(|name| context.var_mappings.contains_key(name))
+    {
+        "`vars` of `config.toml`"
+    } else if name
+        .to_str()
+        .is_some_and(|name| context.var_defaults.contains_key(name))
+    {
+        "default from `vars.toml`"
+    } else if is_builtin() {
+        "built-in default"
+    } else if name == "HOME" {
+        "home directory in the user database"
+    } else if name == "XDG_RUNTIME_DIR" {
+        "built-in default, `/run/user/UID`"
+    } else if name == wsl::WINHOME {
+        "Windows user profile"
+    } else {
+        "not set"
+    }
+}
+
+/// Describes how the package file `original` is linked.
+fn handling(entry: &Path, original: &Path, options: &Options) -> String {
+    let mut handling = String::from(if wsl::is_windows_target(entry) {
+        "copied (under `@WINHOME`)"
+    } else if is_copied(original, false, options) {
+        "copied (matches `copy` in `xdot.toml`)"
+    } else {
+        match options.strategy {
+            LinkStrategy::Symlink => "symlinked",
+            LinkStrategy::RelativeSymlink => "symlinked, relative to its directory",
+            LinkStrategy::Hardlink => "hard linked",
+        }
+    });
+
+    if executables::is_meant_executable(original) {
+        handling.push_str(", made executable (under `@XDG_BIN_HOME`)");
+    }
+
+    handling
+}
+
+/// Describes what is at `link`, and what linking would do about it.
+fn state(original: &Path, link: &Path) -> String {
+    if is_linked(original, link) {
+        return String::from("linked");
+    }
+
+    let Ok(metadata) = link.symlink_metadata() else {
+        return String::from("missing, would be linked");
+    };
+
+    if metadata.is_symlink() {
+        let target = link.read_link().unwrap_or_default();
+
+        return if link.exists() {
+            format!(
+                "symlink to {}, a conflict unless `--interactive` is given",
+                target.display()
+            )
+        } else {
+            format!(
+                "broken symlink to {}, would be replaced unless `--keep-broken` is given",
+                target.display()
+            )
+        };
+    }
+
+    if metadata.is_dir() {
+        return String::from("directory, a conflict");
+    }
+
+    String::from(
+        "regular file, a conflict: see `--force`, `--backup`, `--interactive` or `xdot adopt`",
+    )
+}
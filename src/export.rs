@@ -0,0 +1,563 @@
+//! `xdot export`: translate packages into a format that doesn't require xdot to be applied.
+
+use std::{
+    ffi::OsStr,
+    fmt::Write as _,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+
+use crate::{
+    entry_link_name, is_copy_target, is_excluded, is_package_metadata, is_topic_dir, shell_quote,
+    strip_at_sign_prefix, walk_files, Config, Context, Layout, Options, PackageSpec,
+};
+
+/// Files larger than this aren't embedded in generated scripts.
+const MAX_EMBEDDED_SIZE: u64 = 1024 * 1024;
+
+const HEREDOC_DELIMITER: &str = "XDOT_EOF";
+
+const SCRIPT_HEADER: &str = "#!/bin/sh\n# Generated by `xdot export script`.\nset -eu\n\n";
+
+const SCRIPT_PRELUDE: &str = r#"
+link() {
+    if [ "$(readlink "$2" 2>/dev/null)" = "$1" ]; then
+        return
+    elif [ -e "$2" ] || [ -L "$2" ]; then
+        echo "Skipping $2: already exists" >&2
+    else
+        mkdir -p "$(dirname "$2")"
+        ln -s "$1" "$2"
+        echo "$2 => $1"
+    fi
+}
+
+copy() {
+    if cmp -s "$1" "$2"; then
+        return
+    elif [ -e "$2" ] || [ -L "$2" ]; then
+        echo "Skipping $2: already exists" >&2
+    else
+        mkdir -p "$(dirname "$2")"
+        cp -p "$1" "$2"
+        echo "Copying $1 to $2"
+    fi
+}
+"#;
+
+const PLAYBOOK_PRELUDE: &str = "# Generated by `xdot export ansible`.
+- name: Link dotfiles
+  hosts: all
+  tasks:
+";
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Script,
+    Ansible,
+    Tmpfiles,
+    /// The JSON Schema of `config.toml`, for editors to validate and complete it.
+    JsonSchema,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "script" => Ok(Self::Script),
+            "ansible" => Ok(Self::Ansible),
+            "tmpfiles" => Ok(Self::Tmpfiles),
+            "json-schema" => Ok(Self::JsonSchema),
+            _ => Err(anyhow!(
+                "Unknown format `{format}`, expected `script`, `ansible`, `tmpfiles` or \
+                 `json-schema`"
+            )),
+        }
+    }
+}
+
+/// A package file, along with its link.
+struct File {
+    original: PathBuf,
+    /// Relative to the packages root.
+    relative: PathBuf,
+    /// The environment variable designating the directory of `link`, if any.
+    var: Option<String>,
+    /// Relative to the directory designated by `var`, or to `/`.
+    link: PathBuf,
+    /// Whether the file is copied rather than linked, see [`is_copy_target`].
+    copied: bool,
+}
+
+pub fn run(
+    context: &Context,
+    format: Format,
+    package_spec: PackageSpec,
+    options: &Options,
+) -> Result<()> {
+    let output = match format {
+        Format::Script => script(context, &package_files(context, package_spec, options)?)?,
+        Format::Ansible => playbook(context, &package_files(context, package_spec, options)?)?,
+        Format::Tmpfiles => tmpfiles(context, &package_files(context, package_spec, options)?)?,
+        Format::JsonSchema => serde_json::to_string_pretty(&Config::json_schema())? + "\n",
+    };
+
+    match &options.output {
+        Some(path) => {
+            std::fs::write(path, output)
+                .with_context(|| format!("Unable to write {}", path.display()))?;
+
+            println!("Exported to {}", path.display());
+        }
+        None => print!("{output}"),
+    }
+
+    Ok(())
+}
+
+/// Returns the script of `xdot export script` for the packages of `package_spec`.
+pub fn script_of(
+    context: &Context,
+    package_spec: PackageSpec,
+    options: &Options,
+) -> Result<String> {
+    script(context, &package_files(context, package_spec, options)?)
+}
+
+fn package_files(
+    context: &Context,
+    package_spec: PackageSpec,
+    options: &Options,
+) -> Result<Vec<File>> {
+    let mut files = Vec::new();
+
+    for package in context.packages(package_spec)?.iter() {
+        collect_files(context, package, options, &mut files)?;
+    }
+
+    Ok(files)
+}
+
+fn collect_files(
+    context: &Context,
+    package: &OsStr,
+    options: &Options,
+    files: &mut Vec<File>,
+) -> Result<()> {
+    let package_path = context.package_path(package);
+
+    collect_dir(context, &package_path, Path::new(""), true, options, files)?;
+
+    for (original, target) in &context.package_config(&package_path)?.links {
+        let (base, var) = match target.strip_prefix('@') {
+            Some(var) => {
+                let (name, rest) = var.split_once('/').unwrap_or((var, ""));
+
+                (PathBuf::from(rest), Some(var_name(OsStr::new(name))?))
+            }
+            None => match target.strip_prefix("~/") {
+                Some(rest) => (PathBuf::from(rest), Some("HOME".to_owned())),
+                None if Path::new(target).is_absolute() => (PathBuf::from(target), None),
+                None => layout_base(context, PathBuf::from(target)),
+            },
+        };
+
+        push_files(context, original, &base, var, options, files)?;
+    }
+
+    Ok(())
+}
+
+/// Collects the files of `directory`, the package itself (`is_package`) or a directory organizing
+/// it by topic, whose entries are linked at `prefix`.
+fn collect_dir(
+    context: &Context,
+    directory: &Path,
+    prefix: &Path,
+    is_package: bool,
+    options: &Options,
+    files: &mut Vec<File>,
+) -> Result<()> {
+    for entry in directory
+        .read_dir()
+        .context("Unable to read package content")?
+    {
+        let entry = entry?;
+
+        if (is_package && is_package_metadata(&entry.file_name()))
+            || is_excluded(context, &entry.path(), options)
+        {
+            continue;
+        }
+
+        let name = prefix.join(entry_link_name(&entry.path(), options));
+
+        if strip_at_sign_prefix(&entry.file_name()).is_none()
+            && is_topic_dir(context, &entry.path(), options)
+        {
+            collect_dir(context, &entry.path(), &name, false, options, files)?;
+
+            continue;
+        }
+
+        // Links are resolved on the target machine.
+        let (base, var) = match strip_at_sign_prefix(&entry.file_name()) {
+            Some(var) => (PathBuf::new(), Some(var_name(var)?)),
+            None => layout_base(context, name),
+        };
+
+        push_files(context, &entry.path(), &base, var, options, files)?;
+    }
+
+    Ok(())
+}
+
+/// Returns where the package entry linked at `name`, relative to the layout root, is linked,
+/// along with the variable designating its directory, if any.
+fn layout_base(context: &Context, name: PathBuf) -> (PathBuf, Option<String>) {
+    match context.layout {
+        Layout::Root => (name, None),
+        Layout::Home => (name, Some("HOME".to_owned())),
+        Layout::Stow => (context.layout_root().join(name), None),
+    }
+}
+
+/// Collects the files of the package entry `original`, linked at `base` in the directory
+/// designated by `var`, if any.
+fn push_files(
+    context: &Context,
+    original: &Path,
+    base: &Path,
+    var: Option<String>,
+    options: &Options,
+    files: &mut Vec<File>,
+) -> Result<()> {
+    walk_files(context, original, base, options, &mut |original, link| {
+        files.push(File {
+            original: original.to_owned(),
+            relative: original.strip_prefix(&context.packages_root)?.to_owned(),
+            var: var.clone(),
+            link: Path::new("/").join(link),
+            copied: is_copy_target(original, options),
+        });
+
+        Ok(())
+    })
+}
+
+/// Checks that `name` can be used as a variable name in generated files.
+fn var_name(name: &OsStr) -> Result<String> {
+    match name
+        .to_str()
+        .filter(|name| name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_'))
+    {
+        Some(name) => Ok(name.to_owned()),
+        None => bail!(
+            "`{}` can't be used as a shell variable",
+            name.to_string_lossy()
+        ),
+    }
+}
+
+/// Returns the shell expression of an environment variable, with its default value, if any.
+fn var_expr(context: &Context, name: &str) -> Result<String> {
+    let Some(default) = context.default_var(OsStr::new(name)) else {
+        return Ok(format!("\"${{{name}:?}}\""));
+    };
+
+    // The default is expanded within double quotes.
+    let Some(default) = default
+        .to_str()
+        .filter(|default| !default.contains(['"', '$', '`', '\\', '}']))
+    else {
+        bail!(
+            "The default of `{name}` can't be used in a script: {}",
+            default.display()
+        );
+    };
+
+    Ok(if default.starts_with('/') {
+        format!("\"${{{name}:-{default}}}\"")
+    } else {
+        format!("\"${{{name}:-$HOME/{default}}}\"")
+    })
+}
+
+/// Where exports put the package files on other machines: the packages root, relative to the home
+/// directory if it is in it.
+enum Root {
+    Home(PathBuf),
+    Absolute(PathBuf),
+}
+
+impl Root {
+    fn of(context: &Context) -> Result<Self> {
+        let root = std::path::absolute(&context.packages_root).with_context(|| {
+            format!("Invalid packages root {}", context.packages_root.display())
+        })?;
+
+        Ok(match root.strip_prefix(&context.home) {
+            Ok(relative) if !relative.as_os_str().is_empty() => Self::Home(relative.to_owned()),
+            _ => Self::Absolute(root),
+        })
+    }
+}
+
+fn script(context: &Context, files: &[File]) -> Result<String> {
+    let mut script = String::from(SCRIPT_HEADER);
+
+    let default_root = match Root::of(context)? {
+        Root::Home(relative) => format!("\"$HOME\"/{}", shell_quote(relative.as_os_str())),
+        Root::Absolute(root) => shell_quote(root.as_os_str()),
+    };
+
+    writeln!(
+        script,
+        "root=\"${{XDOT_DIR:-}}\"\n[ -n \"$root\" ] || root={default_root}"
+    )?;
+    script.push_str(SCRIPT_PRELUDE);
+
+    for file in files {
+        let metadata = file.original.metadata()?;
+
+        if metadata.len() > MAX_EMBEDDED_SIZE {
+            bail!(
+                "{} is too large to be embedded ({} bytes)",
+                file.original.display(),
+                metadata.len()
+            );
+        }
+
+        let content = std::fs::read(&file.original)
+            .with_context(|| format!("Unable to read {}", file.original.display()))?;
+
+        let path = format!("\"$root\"/{}", shell_quote(file.relative.as_os_str()));
+
+        writeln!(script)?;
+
+        if let Some(parent) = file.relative.parent() {
+            writeln!(
+                script,
+                "mkdir -p \"$root\"/{}",
+                shell_quote(parent.as_os_str())
+            )?;
+        }
+
+        match std::str::from_utf8(&content) {
+            Ok(text)
+                if text.ends_with('\n') && !text.lines().any(|line| line == HEREDOC_DELIMITER) =>
+            {
+                write!(
+                    script,
+                    "cat > {path} <<'{HEREDOC_DELIMITER}'\n{text}{HEREDOC_DELIMITER}\n"
+                )?;
+            }
+            _ => {
+                script.push_str("printf '");
+
+                for byte in content {
+                    match byte {
+                        b'%' => script.push_str("%%"),
+                        b'\\' | b'\'' => write!(script, "\\{byte:03o}")?,
+                        b' '..=b'~' => script.push(char::from(byte)),
+                        _ => write!(script, "\\{byte:03o}")?,
+                    }
+                }
+
+                writeln!(script, "' > {path}")?;
+            }
+        }
+
+        writeln!(
+            script,
+            "chmod {:o} {path}",
+            metadata.permissions().mode() & 0o7777
+        )?;
+        writeln!(
+            script,
+            "{} {path} {}{}",
+            if file.copied { "copy" } else { "link" },
+            match &file.var {
+                Some(var) => var_expr(context, var)?,
+                None => String::new(),
+            },
+            shell_quote(file.link.as_os_str())
+        )?;
+    }
+
+    Ok(script)
+}
+
+/// Returns the Jinja expression of an environment variable, with its default value, if any.
+fn jinja_var_expr(context: &Context, name: &str) -> Result<String> {
+    let Some(default) = context.default_var(OsStr::new(name)) else {
+        return Ok(format!("{{{{ ansible_env.{name} }}}}"));
+    };
+
+    // The default is a single quoted Jinja string.
+    let Some(default) = default
+        .to_str()
+        .filter(|default| !default.contains(['\'', '\\', '{', '}']))
+    else {
+        bail!(
+            "The default of `{name}` can't be used in a playbook: {}",
+            default.display()
+        );
+    };
+
+    Ok(if default.starts_with('/') {
+        format!("{{{{ ansible_env.{name} | default('{default}', true) }}}}")
+    } else {
+        format!("{{{{ ansible_env.{name} | default(ansible_env.HOME + '/{default}', true) }}}}")
+    })
+}
+
+/// Returns an Ansible playbook copying the package files to the packages root (relative to the
+/// home directory if it is in it) on managed nodes, then linking them, or copying those which are
+/// copied rather than linked.
+fn playbook(context: &Context, files: &[File]) -> Result<String> {
+    let mut playbook = String::from(PLAYBOOK_PRELUDE);
+
+    let root = match Root::of(context)? {
+        Root::Home(relative) => format!("{{{{ ansible_env.HOME }}}}/{}", relative.display()),
+        Root::Absolute(root) => root.display().to_string(),
+    };
+
+    // YAML is a superset of JSON, whose strings are easier to quote.
+    let quote = |text: &str| serde_json::to_string(text);
+
+    for file in files {
+        let mode = file.original.metadata()?.permissions().mode() & 0o7777;
+
+        let store = |path: &Path| format!("{root}/{}", path.display());
+
+        let path = store(&file.relative);
+        let path_parent = store(file.relative.parent().unwrap_or(Path::new("")));
+
+        let link_prefix = match &file.var {
+            Some(var) => jinja_var_expr(context, var)?,
+            None => String::new(),
+        };
+        let link = format!("{link_prefix}{}", file.link.display());
+        let link_parent = format!(
+            "{link_prefix}{}",
+            file.link.parent().unwrap_or(Path::new("/")).display()
+        );
+
+        let name = file.relative.display().to_string();
+
+        for directory in [&path_parent, &link_parent] {
+            write!(
+                playbook,
+                "    - name: {}\n      ansible.builtin.file:\n        path: {}\n        state: directory\n",
+                quote(&format!("Create {directory}"))?,
+                quote(directory)?,
+            )?;
+        }
+
+        write!(
+            playbook,
+            "    - name: {}\n      ansible.builtin.copy:\n        src: {}\n        dest: {}\n        mode: \"{mode:04o}\"\n",
+            quote(&format!("Copy {name}"))?,
+            quote(&file.original.to_string_lossy())?,
+            quote(&path)?,
+        )?;
+
+        if file.copied {
+            write!(
+                playbook,
+                "    - name: {}\n      ansible.builtin.copy:\n        src: {}\n        dest: {}\n        remote_src: true\n        mode: \"{mode:04o}\"\n",
+                quote(&format!("Copy {name} into place"))?,
+                quote(&path)?,
+                quote(&link)?,
+            )?;
+        } else {
+            write!(
+                playbook,
+                "    - name: {}\n      ansible.builtin.file:\n        src: {}\n        dest: {}\n        state: link\n",
+                quote(&format!("Link {name}"))?,
+                quote(&path)?,
+                quote(&link)?,
+            )?;
+        }
+    }
+
+    Ok(playbook)
+}
+
+/// Returns the `systemd-tmpfiles` specifier of the directory designated by an environment
+/// variable, see tmpfiles.d(5).
+fn tmpfiles_var_expr(context: &Context, name: &str) -> Result<String> {
+    let specifier = match name {
+        "HOME" => "%h",
+        "XDG_CONFIG_HOME" => "%E",
+        "XDG_STATE_HOME" => "%S",
+        "XDG_CACHE_HOME" => "%C",
+        "XDG_RUNTIME_DIR" => "%t",
+        _ => {
+            let Some(default) = context.default_var(OsStr::new(name)) else {
+                bail!("`{name}` has no default, it can't be expressed in tmpfiles.d");
+            };
+
+            return Ok(if default.is_absolute() {
+                tmpfiles_escape(default)?
+            } else {
+                format!("%h/{}", tmpfiles_escape(default)?)
+            });
+        }
+    };
+
+    Ok(specifier.to_owned())
+}
+
+/// Escapes specifiers, failing on whitespace which would split the line.
+fn tmpfiles_escape(path: &Path) -> Result<String> {
+    let path = path.to_string_lossy();
+
+    if path.contains(char::is_whitespace) {
+        bail!("{path} contains whitespace, it can't be expressed in tmpfiles.d");
+    }
+
+    Ok(path.replace('%', "%%"))
+}
+
+/// Returns a user tmpfiles.d configuration creating the links, the package files are expected to
+/// be in the packages root (relative to the home directory if it is in it).
+fn tmpfiles(context: &Context, files: &[File]) -> Result<String> {
+    let mut tmpfiles = String::from("# Generated by `xdot export tmpfiles`.\n");
+
+    let root = match Root::of(context)? {
+        Root::Home(relative) => format!("%h/{}", tmpfiles_escape(&relative)?),
+        Root::Absolute(root) => tmpfiles_escape(&root)?,
+    };
+
+    for file in files {
+        if file.copied {
+            bail!(
+                "{} is copied rather than linked, which can't be expressed in tmpfiles.d",
+                file.original.display()
+            );
+        }
+
+        let link = match &file.var {
+            Some(var) => format!(
+                "{}{}",
+                tmpfiles_var_expr(context, var)?,
+                tmpfiles_escape(&file.link)?
+            ),
+            None => tmpfiles_escape(&file.link)?,
+        };
+
+        writeln!(
+            tmpfiles,
+            "L {link} - - - - {root}/{}",
+            tmpfiles_escape(&file.relative)?
+        )?;
+    }
+
+    Ok(tmpfiles)
+}
@@ -0,0 +1,323 @@
+//! A tiny expression language used to select planned operations, e.g.
+//! `target ~ "\.config/nvim" && action == link`.
+//!
+//! ```text
+//! expr       := and ("||" and)*
+//! and        := unary ("&&" unary)*
+//! unary      := "!" unary | "(" expr ")" | comparison
+//! comparison := field ("==" | "!=" | "~" | "!~") value
+//! field      := "action" | "source" | "target"
+//! value      := word | "\"" string "\""
+//! ```
+//!
+//! `~` and `!~` match (or don't match) a regular expression, inside strings `\"` is the only
+//! escape sequence so that regular expressions can be written verbatim.
+
+use std::path::Path;
+
+use anyhow::{bail, Context as _, Result};
+use regex::Regex;
+
+/// A filesystem operation an expression is evaluated against.
+pub struct Operation<'a> {
+    /// Either `link` or `unlink`.
+    pub action: &'a str,
+    pub source: &'a Path,
+    pub target: &'a Path,
+}
+
+#[derive(Clone, Copy)]
+pub enum Field {
+    Action,
+    Source,
+    Target,
+}
+
+pub enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Eq(Field, String),
+    Match(Field, Regex),
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+
+        let expr = parser.or()?;
+
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected `{}` in filter expression", token.describe());
+        }
+
+        Ok(expr)
+    }
+
+    pub fn matches(&self, operation: &Operation) -> bool {
+        match self {
+            Self::Or(lhs, rhs) => lhs.matches(operation) || rhs.matches(operation),
+            Self::And(lhs, rhs) => lhs.matches(operation) && rhs.matches(operation),
+            Self::Not(expr) => !expr.matches(operation),
+            Self::Eq(field, value) => field.get(operation) == value.as_str(),
+            Self::Match(field, regex) => regex.is_match(&field.get(operation)),
+        }
+    }
+}
+
+impl Field {
+    fn get<'a>(self, operation: &Operation<'a>) -> std::borrow::Cow<'a, str> {
+        match self {
+            Self::Action => operation.action.into(),
+            Self::Source => operation.source.to_string_lossy(),
+            Self::Target => operation.target.to_string_lossy(),
+        }
+    }
+}
+
+enum Token {
+    Word(String),
+    String(String),
+    Symbol(&'static str),
+}
+
+impl Token {
+    fn describe(&self) -> &str {
+        match self {
+            Self::Word(word) => word,
+            Self::String(string) => string,
+            Self::Symbol(symbol) => symbol,
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    const SYMBOLS: &[&str] = &["&&", "||", "==", "!=", "!~", "~", "!", "(", ")"];
+
+    let mut tokens = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if let Some(string) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = string.char_indices();
+
+            let end = loop {
+                match chars.next() {
+                    Some((index, '"')) => break index,
+                    Some((_, '\\')) if chars.as_str().starts_with('"') => {
+                        value.push('"');
+                        chars.next();
+                    }
+                    Some((_, char)) => value.push(char),
+                    None => bail!("Unterminated string in filter expression"),
+                }
+            };
+
+            tokens.push(Token::String(value));
+            rest = &string[end + 1..];
+        } else {
+            let end = rest
+                .find(|char: char| char.is_whitespace() || "&|=!~()\"".contains(char))
+                .unwrap_or(rest.len());
+
+            if end == 0 {
+                bail!("Unexpected character in filter expression: `{rest}`");
+            }
+
+            tokens.push(Token::Word(rest[..end].to_owned()));
+            rest = &rest[end..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<&Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .context("Unexpected end of filter expression")?;
+
+        self.position += 1;
+
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+
+        if self.eat("(") {
+            let expr = self.or()?;
+
+            if !self.eat(")") {
+                bail!("Expected `)` in filter expression");
+            }
+
+            return Ok(expr);
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let field = match self.next()? {
+            Token::Word(word) if word == "action" => Field::Action,
+            Token::Word(word) if word == "source" => Field::Source,
+            Token::Word(word) if word == "target" => Field::Target,
+            token => bail!(
+                "Unknown field `{}` in filter expression, expected one of `action`, `source` or `target`",
+                token.describe()
+            ),
+        };
+
+        let operator = match self.next()? {
+            Token::Symbol(symbol @ ("==" | "!=" | "~" | "!~")) => *symbol,
+            token => bail!(
+                "Expected a comparison operator in filter expression, found `{}`",
+                token.describe()
+            ),
+        };
+
+        let value = match self.next()? {
+            Token::Word(value) | Token::String(value) => value.clone(),
+            Token::Symbol(symbol) => {
+                bail!("Expected a value in filter expression, found `{symbol}`")
+            }
+        };
+
+        Ok(match operator {
+            "==" => Expr::Eq(field, value),
+            "!=" => Expr::Not(Box::new(Expr::Eq(field, value))),
+            "~" => Expr::Match(field, compile(&value)?),
+            _ => Expr::Not(Box::new(Expr::Match(field, compile(&value)?))),
+        })
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).with_context(|| format!("Invalid regular expression `{pattern}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expr: &str, action: &str, target: &str) -> bool {
+        Expr::parse(expr).unwrap().matches(&Operation {
+            action,
+            source: Path::new("/home/me/.xdot/nvim/init.lua"),
+            target: Path::new(target),
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = "action == unlink || action == link && target ~ nvim";
+
+        assert!(matches(expr, "unlink", "/home/me/.zshrc"));
+        assert!(matches(expr, "link", "/home/me/.config/nvim"));
+        assert!(!matches(expr, "link", "/home/me/.zshrc"));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        let expr = "(action == unlink || action == link) && target ~ nvim";
+
+        assert!(!matches(expr, "unlink", "/home/me/.zshrc"));
+        assert!(matches(expr, "unlink", "/home/me/.config/nvim"));
+    }
+
+    #[test]
+    fn negations() {
+        assert!(matches("!(target ~ zsh)", "link", "/home/me/.config/nvim"));
+        assert!(matches("target !~ zsh", "link", "/home/me/.config/nvim"));
+        assert!(matches("action != unlink", "link", "/home/me/.zshrc"));
+        assert!(!matches("!action == link", "link", "/home/me/.zshrc"));
+    }
+
+    #[test]
+    fn strings_escape_quotes_only() {
+        assert!(matches(
+            r#"target ~ "\.config/nvim$""#,
+            "link",
+            "/home/me/.config/nvim"
+        ));
+        assert!(!matches(
+            r#"target ~ "\.config/nvim$""#,
+            "link",
+            "/home/me/_config/nvim"
+        ));
+        assert!(matches(
+            r#"target == "/home/me/\"quoted\"""#,
+            "link",
+            r#"/home/me/"quoted""#
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        for expr in [
+            "action == link &&",
+            "action == link ||",
+            "action ==",
+            "(action == link",
+            "action == link)",
+            "name == link",
+            "target ~ \"unterminated",
+            "target ~ \"(\"",
+        ] {
+            assert!(Expr::parse(expr).is_err(), "{expr}");
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! `xdot freeze`: replace symlinks with copies of the package files they point to, and `xdot thaw`,
+//! its inverse.
+
+use std::{
+    ffi::OsString,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    dot_prefix, is_copy_target, is_excluded, is_linked, skip, term, umask, Context, Options,
+    PackageSpec, SkipReason,
+};
+
+pub fn freeze(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    for package in context.packages(package_spec)?.iter() {
+        println!("Freezing config for `{}`", package.to_string_lossy());
+
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            freeze_or_descend(context, &original, &link, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces `link` with a copy of `original` if it is a symlink to it, or, if `link` is a
+/// directory, descends into it.
+fn freeze_or_descend(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+) -> Result<()> {
+    let Ok(link_metadata) = link.symlink_metadata() else {
+        skip(link, SkipReason::Missing, options);
+
+        return Ok(());
+    };
+
+    if link_metadata.is_symlink() && is_linked(original, link) {
+        println!("Copying {} to {}", original.display(), link.display());
+
+        if !options.dry_run {
+            copy_into_place(original, link, options)?;
+        }
+    } else if link_metadata.is_dir() && original.is_dir() {
+        for entry in original
+            .read_dir()
+            .with_context(|| format!("Unable to descend into {}", original.display()))?
+        {
+            let entry = entry?;
+
+            if is_excluded(context, &entry.path(), options) {
+                continue;
+            }
+
+            freeze_or_descend(
+                context,
+                &entry.path(),
+                &link.join(dot_prefix::link_name(&entry.path(), options)),
+                options,
+            )?;
+        }
+    } else {
+        skip(link, SkipReason::NotLinked, options);
+    }
+
+    Ok(())
+}
+
+pub fn thaw(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    for package in context.packages(package_spec)?.iter() {
+        println!("Thawing config for `{}`", package.to_string_lossy());
+
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            thaw_or_descend(context, &original, &link, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Replaces `link` with a symlink to `original` if it is a copy of it, or, if `link` is a
+/// directory, descends into it.
+fn thaw_or_descend(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+) -> Result<()> {
+    let Ok(link_metadata) = link.symlink_metadata() else {
+        skip(link, SkipReason::Missing, options);
+
+        return Ok(());
+    };
+
+    if link_metadata.is_dir() && original.is_dir() {
+        for entry in original
+            .read_dir()
+            .with_context(|| format!("Unable to descend into {}", original.display()))?
+        {
+            let entry = entry?;
+
+            if is_excluded(context, &entry.path(), options) {
+                continue;
+            }
+
+            thaw_or_descend(
+                context,
+                &entry.path(),
+                &link.join(dot_prefix::link_name(&entry.path(), options)),
+                options,
+            )?;
+        }
+    } else if link_metadata.is_file() && original.is_file() {
+        // Symlinks to them would be conflicts when linking.
+        if is_copy_target(original, options) {
+            skip(link, SkipReason::CopyTarget, options);
+
+            return Ok(());
+        }
+
+        if !same_content(original, link)? {
+            skip(link, SkipReason::Modified, options);
+
+            return Ok(());
+        }
+
+        println!(
+            "{}",
+            term::paint(
+                &term::link_line(link, original, options.verbosity > 0),
+                term::Color::Green
+            )
+        );
+
+        if !options.dry_run {
+            let temporary = temporary_sibling(link);
+
+            symlink(original, &temporary).with_context(|| {
+                format!(
+                    "Unable to symlink {} => {}",
+                    temporary.display(),
+                    original.display()
+                )
+            })?;
+
+            std::fs::rename(&temporary, link)
+                .with_context(|| format!("Unable to replace {}", link.display()))?;
+        }
+    } else {
+        skip(link, SkipReason::NotLinked, options);
+    }
+
+    Ok(())
+}
+
+pub fn same_content(a: &Path, b: &Path) -> Result<bool> {
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let read = |path: &Path| {
+        std::fs::read(path).with_context(|| format!("Unable to read {}", path.display()))
+    };
+
+    Ok(read(a)? == read(b)?)
+}
+
+/// Returns a path next to `path` to stage a replacement in.
+fn temporary_sibling(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".xdot-{}", std::process::id()));
+
+    path.with_file_name(name)
+}
+
+/// Copies `from` to `to` (replacing a symlink there) through a temporary sibling, renamed into
+/// place once written to disk: programs reading `to` never see a partial copy, even if the run is
+/// interrupted.
+pub fn copy_into_place(from: &Path, to: &Path, options: &Options) -> Result<()> {
+    let temporary = temporary_sibling(to);
+
+    if let Err(error) = copy_recursively(from, &temporary, options) {
+        let _ = if temporary.is_dir() {
+            std::fs::remove_dir_all(&temporary)
+        } else {
+            std::fs::remove_file(&temporary)
+        };
+
+        return Err(error);
+    }
+
+    if from.is_dir() && to.symlink_metadata().is_ok() {
+        // `rename` can't replace a symlink with a directory.
+        std::fs::remove_file(to).with_context(|| format!("Unable to remove {}", to.display()))?;
+    }
+
+    std::fs::rename(&temporary, to)
+        .with_context(|| format!("Unable to replace {}", to.display()))?;
+
+    // Makes the rename itself durable, not all filesystems support syncing directories.
+    if let Some(parent) = to.parent() {
+        let _ = std::fs::File::open(parent).and_then(|directory| directory.sync_all());
+    }
+
+    Ok(())
+}
+
+/// Copies `from` to `to`, recursing into directories, preserving permissions and syncing files to
+/// disk.
+pub fn copy_recursively(from: &Path, to: &Path, options: &Options) -> Result<()> {
+    if from.is_dir() {
+        std::fs::create_dir(to).with_context(|| format!("Unable to create {}", to.display()))?;
+        std::fs::set_permissions(to, from.metadata()?.permissions())?;
+        umask::restrict_copy(to, options)?;
+
+        for entry in from
+            .read_dir()
+            .with_context(|| format!("Unable to descend into {}", from.display()))?
+        {
+            let entry = entry?;
+
+            copy_recursively(&entry.path(), &to.join(entry.file_name()), options)?;
+        }
+    } else {
+        std::fs::copy(from, to)
+            .with_context(|| format!("Unable to copy {} to {}", from.display(), to.display()))?;
+        umask::restrict_copy(to, options)?;
+
+        std::fs::File::open(to)
+            .and_then(|file| file.sync_all())
+            .with_context(|| format!("Unable to write {} to disk", to.display()))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! `xdot githook`: (un)install git hooks relinking every package when the packages repository
+//! is updated.
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+
+use crate::{shell_quote, Context, Options};
+
+/// Marks hooks written by xdot, other hooks are never overwritten nor removed.
+const MARKER: &str = "# Installed by `xdot githook install`.";
+
+#[derive(Clone, Copy)]
+pub enum Action {
+    Install,
+    Uninstall,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action {
+            "install" => Ok(Self::Install),
+            "uninstall" => Ok(Self::Uninstall),
+            _ => Err(anyhow!(
+                "Unknown action `{action}`, expected `install` or `uninstall`"
+            )),
+        }
+    }
+}
+
+pub fn run(context: &Context, action: Action, options: &Options) -> Result<()> {
+    let hooks = hooks_dir(&context.packages_root)?;
+    let xdot = std::env::current_exe().context("Unable to locate the xdot executable")?;
+    // Hooks run from the repository, whose packages root may not be the default one.
+    let packages_root = std::path::absolute(&context.packages_root)
+        .with_context(|| format!("Invalid packages root {}", context.packages_root.display()))?;
+
+    // `post-checkout` also runs when checking out files, its third argument is `1` when switching
+    // branches.
+    let hooks = [
+        (hooks.join("post-merge"), String::new()),
+        (
+            hooks.join("post-checkout"),
+            String::from("[ \"$3\" = 1 ] || exit 0\n"),
+        ),
+    ];
+
+    for (path, guard) in hooks {
+        let existing = match std::fs::read_to_string(&path) {
+            Ok(content) => Some(content),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                return Err(error).with_context(|| format!("Unable to read {}", path.display()))
+            }
+        };
+
+        if existing
+            .as_ref()
+            .is_some_and(|content| !content.contains(MARKER))
+        {
+            bail!(
+                "{} already exists and wasn't installed by xdot",
+                path.display()
+            );
+        }
+
+        match action {
+            Action::Install => {
+                println!("Writing hook: {}", path.display());
+
+                if !options.dry_run {
+                    let script = format!(
+                        "#!/bin/sh\n{MARKER}\n{guard}exec {} --dir {} --all\n",
+                        shell_quote(xdot.as_os_str()),
+                        shell_quote(packages_root.as_os_str())
+                    );
+
+                    std::fs::write(&path, script)
+                        .with_context(|| format!("Unable to write {}", path.display()))?;
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                        .with_context(|| format!("Unable to make {} executable", path.display()))?;
+                }
+            }
+            Action::Uninstall if existing.is_some() => {
+                println!("Removing hook: {}", path.display());
+
+                if !options.dry_run {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("Unable to remove {}", path.display()))?;
+                }
+            }
+            Action::Uninstall => {
+                if options.verbosity > 0 {
+                    println!("Skipping non-existent hook: {}", path.display());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the hooks directory of the repository at `repository`, honoring `core.hooksPath`.
+fn hooks_dir(repository: &Path) -> Result<PathBuf> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(["rev-parse", "--path-format=absolute", "--git-path", "hooks"])
+        .output()
+        .context("Unable to execute git command")?;
+
+    if !output.status.success() {
+        bail!(
+            "{} is not a git repository: {}",
+            repository.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let hooks = PathBuf::from(String::from_utf8(output.stdout)?.trim_end());
+
+    std::fs::create_dir_all(&hooks)
+        .with_context(|| format!("Unable to create {}", hooks.display()))?;
+
+    Ok(hooks)
+}
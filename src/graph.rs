@@ -0,0 +1,117 @@
+//! `xdot graph`: print the package → file → target mapping as a Graphviz (or mermaid) graph.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{walk_files, Context, Options, PackageSpec};
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let mut edges = Vec::<(String, PathBuf, PathBuf)>::new();
+
+    for package in context.packages(package_spec)?.iter() {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                edges.push((
+                    package.to_string_lossy().into_owned(),
+                    original.to_owned(),
+                    link.to_owned(),
+                ));
+
+                Ok(())
+            })?;
+        }
+    }
+
+    let mut owners = HashMap::<&Path, usize>::new();
+
+    for (_, _, link) in &edges {
+        *owners.entry(link).or_default() += 1;
+    }
+
+    let is_collision = |link: &Path| owners[link] > 1;
+
+    if options.mermaid {
+        let mut ids = HashMap::<String, usize>::new();
+        let mut node = |key: String| {
+            let next = ids.len();
+            let id = *ids.entry(key).or_insert(next);
+
+            (format!("n{id}"), id == next)
+        };
+
+        println!("flowchart LR");
+
+        for (package, original, link) in &edges {
+            let (package_id, new_package) = node(format!("package:{package}"));
+            let (original_id, new_original) = node(original.display().to_string());
+            let (link_id, new_link) = node(link.display().to_string());
+
+            if new_package {
+                println!("    {package_id}[{}]", mermaid_label(package));
+            }
+
+            if new_original {
+                println!(
+                    "    {original_id}[{}]",
+                    mermaid_label(&original.display().to_string())
+                );
+            }
+
+            if new_link {
+                println!(
+                    "    {link_id}([{}])",
+                    mermaid_label(&link.display().to_string())
+                );
+            }
+
+            println!("    {package_id} --> {original_id}");
+
+            if is_collision(link) {
+                println!("    {original_id} -. collision .-> {link_id}");
+                println!("    style {link_id} stroke:red");
+            } else {
+                println!("    {original_id} --> {link_id}");
+            }
+        }
+    } else {
+        println!("digraph xdot {{");
+        println!("    rankdir=LR;");
+
+        for (package, original, link) in &edges {
+            let package_id = dot_quote(&format!("package:{package}"));
+            let original_id = dot_quote(&original.display().to_string());
+            let link_id = dot_quote(&link.display().to_string());
+
+            println!(
+                "    {package_id} [shape=box, label={}];",
+                dot_quote(package)
+            );
+            println!("    {package_id} -> {original_id};");
+
+            if is_collision(link) {
+                println!("    {link_id} [color=red];");
+                println!("    {original_id} -> {link_id} [color=red, label=\"collision\"];");
+            } else {
+                println!("    {original_id} -> {link_id};");
+            }
+        }
+
+        println!("}}");
+    }
+
+    Ok(())
+}
+
+/// Quotes `id` as a DOT string.
+fn dot_quote(id: &str) -> String {
+    format!("\"{}\"", id.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Quotes `label` as a mermaid node label.
+fn mermaid_label(label: &str) -> String {
+    format!("\"{}\"", label.replace('"', "#quot;"))
+}
@@ -0,0 +1,194 @@
+//! `--unlink --interactive`: pick which of the links of the packages to remove, so that only part
+//! of a configuration goes back to local management.
+//!
+//! When linking, `--interactive` asks how to resolve each conflict rather than failing.
+
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{diff, dot_prefix, is_excluded, is_linked, Context, Options};
+
+/// How to resolve a conflict, as answered with `--interactive`.
+pub enum Resolution {
+    /// Remove the existing file (backing it up with `--backup`), then link.
+    Overwrite,
+    /// Back up the existing file, then link.
+    Backup,
+    /// Move the existing file over the package file, then link.
+    Adopt,
+    Skip,
+}
+
+/// Asks how to resolve the conflict between the package file `original` and what is at `link`:
+/// the file `existing`, or a symlink to another file.
+pub fn resolve(
+    original: &Path,
+    link: &Path,
+    existing: Option<&Path>,
+    options: &Options,
+) -> Result<Resolution> {
+    // None of the answers is safe to pick on the user's behalf, nor is there a default.
+    if options.yes || options.no_input {
+        bail!(
+            "{} already exists, conflicts aren't resolved with `{}`, see `--force` or `--backup`",
+            link.display(),
+            if options.yes { "--yes" } else { "--no-input" }
+        );
+    }
+
+    let choices = if existing.is_some() {
+        "[o]verwrite, [b]ackup+overwrite, [a]dopt into package, [s]kip, [d]iff, [q]uit"
+    } else {
+        "[o]verwrite, [s]kip, [q]uit"
+    };
+
+    loop {
+        print!("{} already exists: {choices}? ", link.display());
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+
+        if std::io::stdin()
+            .read_line(&mut answer)
+            .context("Unable to read answer")?
+            == 0
+        {
+            bail!("Aborted");
+        }
+
+        match (answer.trim(), existing) {
+            ("o", _) => return Ok(Resolution::Overwrite),
+            ("b", Some(_)) => return Ok(Resolution::Backup),
+            ("a", Some(_)) => return Ok(Resolution::Adopt),
+            ("s", _) => return Ok(Resolution::Skip),
+            ("d", Some(existing)) => {
+                if diff::show_conflict(original, existing) {
+                    println!("Same content as {}.", original.display());
+                }
+            }
+            ("q", _) => bail!("Aborted"),
+            (answer, _) => println!("Invalid answer `{answer}`"),
+        }
+    }
+}
+
+/// Lists the links of `packages` and asks which to remove, all of them with `--yes`, none with
+/// `--no-input`.
+pub fn select(
+    context: &Context,
+    packages: &[Box<OsStr>],
+    options: &Options,
+) -> Result<HashSet<PathBuf>> {
+    let mut links = Vec::new();
+
+    for package in packages {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            managed_links(context, &original, &link, options, &mut links);
+        }
+    }
+
+    if links.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    if options.yes {
+        return Ok(links.into_iter().collect());
+    }
+
+    if options.no_input {
+        return Ok(HashSet::new());
+    }
+
+    for (index, link) in links.iter().enumerate() {
+        println!("{:>4}. {}", index + 1, link.display());
+    }
+
+    loop {
+        print!("Unlink which? (e.g. `1 3-5`, `all`, nothing for none) ");
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .context("Unable to read answer")?;
+
+        match parse_selection(answer.trim(), links.len()) {
+            Ok(indices) => {
+                return Ok(indices
+                    .into_iter()
+                    .map(|index| links[index].clone())
+                    .collect())
+            }
+            Err(error) => println!("{error}"),
+        }
+    }
+}
+
+/// Collects the links which resolve to `original`, at `link` or below it, the way linking laid
+/// them out.
+fn managed_links(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    links: &mut Vec<PathBuf>,
+) {
+    if is_linked(original, link) {
+        links.push(link.to_owned());
+
+        return;
+    }
+
+    if !original.is_dir() || !link.is_dir() {
+        return;
+    }
+
+    let Ok(entries) = original.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if is_excluded(context, &entry.path(), options) {
+            continue;
+        }
+
+        managed_links(
+            context,
+            &entry.path(),
+            &link.join(dot_prefix::link_name(&entry.path(), options)),
+            options,
+            links,
+        );
+    }
+}
+
+/// Parses space or comma separated numbers and ranges, starting at 1, into indices.
+fn parse_selection(selection: &str, len: usize) -> Result<Vec<usize>> {
+    if selection == "all" {
+        return Ok((0..len).collect());
+    }
+
+    let mut indices = Vec::new();
+
+    for item in selection.split([' ', ',']).filter(|item| !item.is_empty()) {
+        let (start, end) = item.split_once('-').unwrap_or((item, item));
+
+        let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>()) else {
+            bail!("Invalid selection `{item}`");
+        };
+
+        if start == 0 || start > end || end > len {
+            bail!("Invalid selection `{item}`, expected numbers from 1 to {len}");
+        }
+
+        indices.extend(start - 1..end);
+    }
+
+    Ok(indices)
+}
@@ -0,0 +1,55 @@
+//! `links.toml`: link package files to arbitrary targets rather than relative to their top-level
+//! entry, e.g.:
+//!
+//! ```toml
+//! "bin/tool" = "@XDG_BIN_HOME/tool"
+//! "units/foo.service" = "~/.config/systemd/user/foo.service"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{read_toml, Context};
+
+pub const FILE: &str = "links.toml";
+
+/// Reads the `links.toml` of the package at `package_path`: package files, joined to it, along
+/// with their targets.
+pub fn read(package_path: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let path = package_path.join(FILE);
+
+    let links: BTreeMap<PathBuf, String> = read_toml(&path)?.unwrap_or_default();
+
+    links
+        .into_iter()
+        .map(|(source, target)| {
+            if source.is_absolute() || source.components().any(|c| c.as_os_str() == "..") {
+                bail!(
+                    "Invalid {}: `{}` isn't a path inside the package",
+                    path.display(),
+                    source.display()
+                );
+            }
+
+            Ok((package_path.join(source), target))
+        })
+        .collect()
+}
+
+/// Returns whether the package file `original` is linked to the target given by `links.toml`.
+pub fn is_mapped(context: &Context, original: &Path) -> bool {
+    context
+        .package_config_of(original)
+        .is_some_and(|config| config.links.contains_key(original))
+}
+
+/// Returns whether package files in the package directory `original` are mapped by `links.toml`.
+pub fn maps_below(context: &Context, original: &Path) -> bool {
+    context
+        .package_config_of(original)
+        .is_some_and(|config| config.links.keys().any(|path| path.starts_with(original)))
+}
@@ -0,0 +1,120 @@
+//! `xdot list` and `xdot search`: show packages, along with the summary of their `README.md`.
+
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+use anyhow::{Context as _, Result};
+
+use crate::{is_vcs_dir, Context, Options, PackageSpec};
+
+/// The documentation of a package, which isn't linked.
+pub const README: &str = "README.md";
+
+/// The first heading and paragraph of a package's `README.md`.
+struct Summary {
+    heading: Option<String>,
+    paragraph: Option<String>,
+}
+
+impl Summary {
+    fn read(package_path: &Path) -> Option<Self> {
+        let readme = std::fs::read_to_string(package_path.join(README)).ok()?;
+
+        let heading = readme
+            .lines()
+            .find_map(|line| line.strip_prefix('#'))
+            .map(|heading| heading.trim_start_matches('#').trim().to_owned());
+
+        let paragraph = readme
+            .split("\n\n")
+            .map(str::trim)
+            .find(|block| !block.is_empty() && !block.starts_with('#'))
+            .map(|block| block.split_whitespace().collect::<Vec<_>>().join(" "));
+
+        Some(Self { heading, paragraph })
+    }
+
+    fn print(&self) {
+        for line in [&self.heading, &self.paragraph].into_iter().flatten() {
+            println!("  {line}");
+        }
+    }
+}
+
+pub fn list(context: &Context, options: &Options) -> Result<()> {
+    let packages = context.packages(PackageSpec::All)?;
+
+    for package in packages.iter() {
+        println!("{}", package.to_string_lossy());
+
+        if options.long {
+            if let Some(summary) = Summary::read(&context.package_path(package)) {
+                summary.print();
+            }
+        }
+    }
+
+    // On stderr, so that the list itself stays easy to use in scripts.
+    for (name, reason) in excluded(context, &packages)? {
+        eprintln!("Excluded: {} ({reason})", name.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Lists the directories of the packages root which aren't among `packages`, found by `--all`,
+/// along with why.
+fn excluded(context: &Context, packages: &[Box<OsStr>]) -> Result<Vec<(Box<OsStr>, &'static str)>> {
+    let mut excluded = Vec::new();
+
+    for entry in context.packages_root.read_dir().with_context(|| {
+        format!(
+            "Unable to list packages ({})",
+            context.packages_root.display()
+        )
+    })? {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        if !entry.file_type()?.is_dir()
+            || is_vcs_dir(&name)
+            || packages.iter().any(|package| **package == *name)
+        {
+            continue;
+        }
+
+        let reason = if name.as_bytes().starts_with(b".") && !context.hidden_packages {
+            "hidden, see `--hidden-packages`"
+        } else {
+            "ignored by `.xdotignore`, `.gitignore`, `.ignore` or git excludes"
+        };
+
+        excluded.push((name.into_boxed_os_str(), reason));
+    }
+
+    excluded.sort();
+
+    Ok(excluded)
+}
+
+/// Lists the packages whose name or `README.md` contains `term`, ignoring case.
+pub fn search(context: &Context, term: &str) -> Result<()> {
+    let term = term.to_lowercase();
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        let package_path = context.package_path(package);
+
+        let readme = std::fs::read_to_string(package_path.join(README)).unwrap_or_default();
+
+        if package.to_string_lossy().to_lowercase().contains(&term)
+            || readme.to_lowercase().contains(&term)
+        {
+            println!("{}", package.to_string_lossy());
+
+            if let Some(summary) = Summary::read(&package_path) {
+                summary.print();
+            }
+        }
+    }
+
+    Ok(())
+}
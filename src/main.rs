@@ -2,16 +2,73 @@
 compile_error!("`xdot` only supports Unix.");
 
 use std::{
-    ffi::OsStr,
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    ops::Bound,
     os::unix::{
         ffi::OsStrExt,
-        fs::{symlink, MetadataExt},
+        fs::{symlink, FileTypeExt, MetadataExt},
     },
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
 };
 
-use anyhow::{anyhow, bail, Context, Result};
-use ignore::WalkBuilder;
+use anyhow::{anyhow, bail, Context as _, Result};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    WalkBuilder,
+};
+use interactive::Resolution;
+use overlay::Overlay;
+use serde::{Deserialize, Serialize};
+
+mod adopt;
+mod backup;
+mod bench;
+mod blame;
+mod check;
+mod completions;
+mod conflicts;
+mod deploy;
+mod diff;
+mod doctor;
+mod dot_prefix;
+mod executables;
+mod explain;
+mod export;
+mod filter;
+mod freeze;
+mod githook;
+mod graph;
+mod interactive;
+mod links;
+mod list;
+mod modes;
+mod new;
+mod notify;
+mod orphans;
+mod overlay;
+mod ownership;
+mod parents;
+mod plugin;
+mod prune;
+mod report;
+mod retry;
+mod scan;
+mod state;
+mod status;
+mod subset;
+mod targets;
+mod term;
+mod transient;
+mod umask;
+mod verify;
+mod wsl;
+mod xdotignore;
 
 /// Flattens literals into a single static string slice, placing a newline between each element.
 macro_rules! joinln {
@@ -20,6 +77,188 @@ macro_rules! joinln {
     };
 }
 
+enum Command {
+    Link,
+    Scan,
+    Diff,
+    GitHook(Option<githook::Action>),
+    Graph,
+    Report,
+    Freeze,
+    Thaw,
+    Deploy(Option<OsString>),
+    Export(Option<export::Format>),
+    Targets,
+    Status,
+    Check,
+    Prune,
+    Restow,
+    Orphans,
+    Backups,
+    Restore(Option<OsString>),
+    Retry,
+    Doctor,
+    VerifyRepo,
+    List,
+    Search(Option<String>),
+    Blame(Option<PathBuf>),
+    Explain(Option<PathBuf>),
+    State(Option<state::Action>),
+    New(Option<OsString>),
+    Adopt {
+        package: Option<OsString>,
+        paths: Vec<PathBuf>,
+    },
+    Completions(Option<completions::Shell>),
+    /// Hidden from `--help`, see [`bench`].
+    Bench,
+    /// An `xdot-<name>` executable found in `$PATH`.
+    External {
+        name: OsString,
+        program: PathBuf,
+        args: Vec<OsString>,
+    },
+}
+
+impl Command {
+    /// Returns the built-in command named `name`, if any.
+    fn from_name(name: &OsStr) -> Option<Self> {
+        match name.to_str()? {
+            "scan" => Some(Self::Scan),
+            "diff" => Some(Self::Diff),
+            "githook" => Some(Self::GitHook(None)),
+            "graph" => Some(Self::Graph),
+            "report" => Some(Self::Report),
+            "freeze" => Some(Self::Freeze),
+            "thaw" => Some(Self::Thaw),
+            "deploy" => Some(Self::Deploy(None)),
+            "export" => Some(Self::Export(None)),
+            "targets" => Some(Self::Targets),
+            "status" => Some(Self::Status),
+            "check" => Some(Self::Check),
+            "prune" => Some(Self::Prune),
+            "restow" => Some(Self::Restow),
+            "orphans" => Some(Self::Orphans),
+            "backups" => Some(Self::Backups),
+            "restore" => Some(Self::Restore(None)),
+            "retry" => Some(Self::Retry),
+            "doctor" => Some(Self::Doctor),
+            "verify-repo" => Some(Self::VerifyRepo),
+            "list" => Some(Self::List),
+            "search" => Some(Self::Search(None)),
+            "blame" => Some(Self::Blame(None)),
+            "explain" => Some(Self::Explain(None)),
+            "state" => Some(Self::State(None)),
+            "new" => Some(Self::New(None)),
+            "adopt" => Some(Self::Adopt {
+                package: None,
+                paths: Vec::new(),
+            }),
+            "completions" => Some(Self::Completions(None)),
+            "bench" => Some(Self::Bench),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the command operates on packages given as arguments or via `--all`.
+    fn takes_packages(&self) -> bool {
+        matches!(
+            self,
+            Self::Link
+                | Self::Diff
+                | Self::Graph
+                | Self::Report
+                | Self::Freeze
+                | Self::Thaw
+                | Self::Deploy(Some(_))
+                | Self::Export(Some(
+                    export::Format::Script | export::Format::Ansible | export::Format::Tmpfiles
+                ))
+                | Self::Targets
+                | Self::Status
+                | Self::Check
+                | Self::Prune
+                | Self::Restow
+                | Self::Orphans
+        )
+    }
+}
+
+/// What to do with package files which are broken symlinks or can't be read.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DanglingPolicy {
+    /// Skip them with a warning.
+    Warn,
+    Error,
+    /// Link them anyway.
+    Link,
+}
+
+impl FromStr for DanglingPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            "link" => Ok(Self::Link),
+            _ => Err(anyhow!(
+                "Unknown policy `{policy}`, expected `warn`, `error` or `link`"
+            )),
+        }
+    }
+}
+
+/// How package files are linked to their targets.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum LinkStrategy {
+    Symlink,
+    /// Symlinks relative to their directory.
+    RelativeSymlink,
+    /// Hard links, directories are created rather than linked.
+    Hardlink,
+}
+
+/// How package entries which aren't `@`-prefixed map to their targets.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Layout {
+    /// Relative to `/`.
+    #[default]
+    Root,
+    /// Relative to `$HOME`.
+    Home,
+    /// Relative to the parent of the packages root, like GNU Stow.
+    Stow,
+}
+
+impl Layout {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Root => "root",
+            Self::Home => "home",
+            Self::Stow => "stow",
+        }
+    }
+}
+
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(layout: &str) -> Result<Self> {
+        match layout {
+            "root" => Ok(Self::Root),
+            "home" => Ok(Self::Home),
+            "stow" => Ok(Self::Stow),
+            _ => Err(anyhow!(
+                "Unknown layout `{layout}`, expected `root`, `home` or `stow`"
+            )),
+        }
+    }
+}
+
 enum PackageSpec {
     None,
     All,
@@ -30,9 +269,87 @@ struct Options {
     verbosity: u8,
     unlink: bool,
     dry_run: bool,
+    filter: Option<filter::Expr>,
+    /// See [`subset`].
+    only: Vec<String>,
+    exclude: Vec<String>,
+    /// Built from `only` and `exclude` once the packages root is found, when any glob is given.
+    subset: Option<subset::Subset>,
+    /// The packages with [`xdotignore`] patterns, read once the packages root is found.
+    ignored: Vec<(PathBuf, Gitignore)>,
+    vcs: bool,
+    mermaid: bool,
+    output: Option<PathBuf>,
+    snapshot: Option<OsString>,
+    /// The command run with `--notify[=CMD]`, empty for `notify-send`.
+    notify: Option<OsString>,
+    all_hosts: bool,
+    /// Where `xdot deploy` copies packages on remote hosts, with `--remote-dir`.
+    remote_dir: Option<OsString>,
+    /// The host `xdot status` reports on, with `--host`, see [`deploy`].
+    host: Option<OsString>,
+    quick: bool,
+    keep_broken: bool,
+    /// Link files one by one rather than whole directories, with `--no-folding`.
+    no_folding: bool,
+    conflicts_report: Option<PathBuf>,
+    install: bool,
+    include_vcs: bool,
+    dir: Option<PathBuf>,
+    target: Option<PathBuf>,
+    layout: Option<Layout>,
+    follow_dir_symlinks: bool,
+    hidden_packages: bool,
+    security: bool,
+    dangling_sources: DanglingPolicy,
+    long: bool,
+    output_sh: bool,
+    summary: bool,
+    keep_going: bool,
+    /// See [`transient`], filled with the config file's when not given.
+    retries: Option<u32>,
+    /// See [`umask`], filled with the config file's when not given.
+    umask: Option<u32>,
+    allow_foreign_dirs: bool,
+    template: Option<String>,
+    /// The size of the package generated by `xdot bench`.
+    files: Option<usize>,
+    depth: Option<usize>,
+    /// Remove old backups with `xdot backups --prune`, see [`backup::prune`].
+    prune: bool,
+    /// The number of backups kept by `--prune`, from `--keep N`.
+    keep: Option<usize>,
+    /// The age in days beyond which `--prune` removes backups, from `--max-age DAYS`.
+    max_age: Option<u64>,
+    strategy: LinkStrategy,
+    /// Overrides of environment variables, from `--env`.
+    env: Vec<(OsString, OsString)>,
+    force: bool,
+    /// Where replaced files are moved with `--backup[=DIR]`, `DIR` being empty by default.
+    backup: Option<PathBuf>,
+    /// The directory the run backs up into, when linking with `--backup`, `--interactive` or
+    /// `--force`.
+    backup_session: Option<PathBuf>,
+    /// Pick which links to remove with `--unlink`, otherwise resolve each conflict.
+    interactive: bool,
+    /// Answer yes to prompts rather than reading stdin, see [`prompt`].
+    yes: bool,
+    /// Take the default answer of prompts rather than reading stdin, see [`prompt`].
+    no_input: bool,
+    /// The links picked with `--interactive`, only those are removed.
+    selection: Option<HashSet<PathBuf>>,
+    /// The packages root once found, for directories folded into it to be told apart.
+    packages_root: Option<PathBuf>,
+    /// Package files copied rather than linked, from `copy` in `xdot.toml`.
+    copied: Option<Gitignore>,
+    /// The package directories `dot-` prefixes are translated in, from `dot_prefix` in `xdot.toml`.
+    dot_prefixed: Vec<PathBuf>,
+    /// See [`parents::record`].
+    parents_record: Option<PathBuf>,
 }
 
 struct Args {
+    command: Command,
     package_spec: PackageSpec,
     options: Options,
 }
@@ -42,40 +359,361 @@ struct Args {
 impl Default for Args {
     fn default() -> Self {
         Self {
+            command: Command::Link,
             package_spec: PackageSpec::None,
             options: Options {
                 verbosity: 0,
                 unlink: false,
                 dry_run: false,
+                filter: None,
+                only: Vec::new(),
+                exclude: Vec::new(),
+                subset: None,
+                ignored: Vec::new(),
+                vcs: false,
+                mermaid: false,
+                output: None,
+                snapshot: None,
+                notify: None,
+                all_hosts: false,
+                remote_dir: None,
+                host: None,
+                quick: false,
+                keep_broken: false,
+                no_folding: false,
+                conflicts_report: None,
+                install: false,
+                include_vcs: false,
+                dir: None,
+                target: None,
+                layout: None,
+                follow_dir_symlinks: false,
+                hidden_packages: false,
+                security: false,
+                dangling_sources: DanglingPolicy::Warn,
+                long: false,
+                output_sh: false,
+                summary: false,
+                keep_going: false,
+                retries: None,
+                umask: None,
+                allow_foreign_dirs: false,
+                template: None,
+                files: None,
+                depth: None,
+                prune: false,
+                keep: None,
+                max_age: None,
+                strategy: LinkStrategy::Symlink,
+                env: Vec::new(),
+                force: false,
+                backup: None,
+                backup_session: None,
+                interactive: false,
+                yes: false,
+                no_input: false,
+                selection: None,
+                packages_root: None,
+                copied: None,
+                dot_prefixed: Vec::new(),
+                parents_record: None,
             },
         }
     }
 }
 
 impl Args {
-    fn from_env() -> Result<Self> {
+    /// Parses the arguments of the process. With `plugins`, the first value which isn't an option
+    /// runs `xdot-<value>` when it is in `$PATH`, see [`plugin`].
+    fn from_env(plugins: bool) -> Result<Self> {
         let mut args = Self::default();
 
-        let mut parser = lexopt::Parser::from_env();
+        let mut raw_args = std::env::args_os().skip(1).peekable();
 
-        while let Some(arg) = parser.next()? {
-            use lexopt::Arg;
+        // Commands must be the first argument, so that `xdot -- scan` still refers to a package.
+        if let Some(command) = raw_args.peek().and_then(|arg| Command::from_name(arg)) {
+            args.command = command;
+            raw_args.next();
+        }
+
+        let mut parser = lexopt::Parser::from_args(raw_args);
+
+        loop {
+            use lexopt::{Arg, ValueExt};
+
+            // Options before the name of a plugin are xdot's (e.g. `--dir`), those after are the
+            // plugin's. `--` is never a plugin name, so `xdot -- name` links a package.
+            if plugins
+                && matches!(args.command, Command::Link)
+                && matches!(args.package_spec, PackageSpec::None)
+            {
+                if let Some(mut raw_args) = parser.try_raw_args() {
+                    if let Some(program) = raw_args.peek().and_then(plugin::find) {
+                        let name = raw_args.next().unwrap_or_default();
+
+                        args.command = Command::External {
+                            name,
+                            program,
+                            args: raw_args.collect(),
+                        };
+
+                        return Ok(args);
+                    }
+                }
+            }
+
+            let Some(arg) = parser.next()? else {
+                break;
+            };
 
             match arg {
                 Arg::Long("dry-run") => args.options.dry_run = true,
-                Arg::Long("unlink") => args.options.unlink = true,
+                Arg::Long("yes") => args.options.yes = true,
+                Arg::Long("no-input") => args.options.no_input = true,
+                Arg::Long("dir") => args.options.dir = Some(parser.value()?.into()),
+                Arg::Long("target") => args.options.target = Some(parser.value()?.into()),
+                Arg::Long("layout") => {
+                    args.options.layout = Some(parser.value()?.string()?.parse()?);
+                }
+                Arg::Long("env") => {
+                    let assignment = parser.value()?;
+                    let bytes = assignment.as_bytes();
+
+                    let Some(index) = bytes
+                        .iter()
+                        .position(|&byte| byte == b'=')
+                        .filter(|&index| index > 0)
+                    else {
+                        bail!(
+                            "Invalid `--env` value `{}`, expected `VAR=value`",
+                            assignment.to_string_lossy()
+                        );
+                    };
+
+                    args.options.env.push((
+                        OsStr::from_bytes(&bytes[..index]).to_owned(),
+                        OsStr::from_bytes(&bytes[index + 1..]).to_owned(),
+                    ));
+                }
+                Arg::Long("unlink") if matches!(args.command, Command::Link | Command::Retry) => {
+                    args.options.unlink = true
+                }
+                Arg::Long("quick") if matches!(args.command, Command::Link | Command::Restow) => {
+                    args.options.quick = true;
+                }
+                Arg::Long("follow-dir-symlinks")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.follow_dir_symlinks = true;
+                }
+                Arg::Long("relative")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.strategy = LinkStrategy::RelativeSymlink;
+                }
+                Arg::Long("hardlink")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.strategy = LinkStrategy::Hardlink;
+                }
+                Arg::Long("interactive") if matches!(args.command, Command::Link) => {
+                    args.options.interactive = true;
+                }
+                Arg::Long("force") if matches!(args.command, Command::Link | Command::Restow) => {
+                    args.options.force = true;
+                }
+                Arg::Long("backup")
+                    if matches!(
+                        args.command,
+                        Command::Link | Command::Restow | Command::Backups | Command::Restore(_)
+                    ) =>
+                {
+                    args.options.backup = Some(
+                        parser
+                            .optional_value()
+                            .map_or_else(PathBuf::new, PathBuf::from),
+                    );
+                }
+                Arg::Long("keep-broken")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.keep_broken = true;
+                }
+                Arg::Long("no-folding")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.no_folding = true;
+                }
+                Arg::Long("dangling-sources")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.dangling_sources = parser.value()?.string()?.parse()?;
+                }
+                Arg::Long("output") if matches!(args.command, Command::Link | Command::Restow) => {
+                    let format = parser.value()?.string()?;
+
+                    if format != "sh" {
+                        bail!("Unknown output format `{format}`, expected `sh`");
+                    }
+
+                    // The commands are printed instead of being run.
+                    args.options.output_sh = true;
+                    args.options.dry_run = true;
+                }
+                Arg::Long("include-vcs")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.include_vcs = true;
+                }
+                Arg::Long("conflicts-report")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.conflicts_report = Some(parser.value()?.into());
+                }
+                Arg::Long("snapshot")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.snapshot = Some(parser.value()?);
+                }
+                Arg::Long("notify") if matches!(args.command, Command::Link | Command::Restow) => {
+                    args.options.notify = Some(parser.optional_value().unwrap_or_default());
+                }
+                Arg::Long("umask") => {
+                    args.options.umask = Some(umask::parse(&parser.value()?.string()?)?);
+                }
+                Arg::Long("only") => args.options.only.push(parser.value()?.string()?),
+                Arg::Long("exclude") => args.options.exclude.push(parser.value()?.string()?),
+                Arg::Long("filter") => {
+                    args.options.filter = Some(filter::Expr::parse(&parser.value()?.string()?)?);
+                }
+                Arg::Long("hidden-packages") => args.options.hidden_packages = true,
                 Arg::Long("verbose") | Arg::Short('v') => {
                     args.options.verbosity = args.options.verbosity.saturating_add(1);
                 }
                 Arg::Long("help") | Arg::Short('h') => {
                     println!(joinln!(
-                        "Usage: xdot [options] [--] [package...]",
+                        "Usage: xdot [options] [--] [package...|-]",
+                        "       xdot scan [options]",
+                        "       xdot diff --vcs [options] [--] [package...]",
+                        "       xdot githook (install|uninstall) [options]",
+                        "       xdot graph [--mermaid] [options] [--] [package...]",
+                        "       xdot report [-o FILE] [options] [--] [package...]",
+                        "       xdot freeze [options] [--] [package...]",
+                        "       xdot thaw [options] [--] [package...]",
+                        "       xdot deploy [--remote-dir DIR] [options] HOST [--] [package...]",
+                        "       xdot deploy --all-hosts [--remote-dir DIR] [options]",
+                        "       xdot export (script|ansible|tmpfiles) [-o FILE] [options] [--] [package...]",
+                        "       xdot export json-schema [-o FILE] [options]",
+                        "       xdot targets [options] [--] [package...]",
+                        "       xdot status [--host HOST] [options] [--] [package...]",
+                        "       xdot check [--summary] [options] [--] [package...]",
+                        "       xdot prune [options] [--] [package...]",
+                        "       xdot restow [options] [--] [package...]",
+                        "       xdot orphans [options] [--] [package...]",
+                        "       xdot backups [--backup=DIR] [options]",
+                        "       xdot backups --prune [--keep N] [--max-age DAYS] [--backup=DIR] [options]",
+                        "       xdot restore [--backup=DIR] [options] ID",
+                        "       xdot retry [--unlink] [options]",
+                        "       xdot doctor [--security] [options]",
+                        "       xdot verify-repo [options]",
+                        "       xdot list [-l|--long] [options]",
+                        "       xdot search [options] TERM",
+                        "       xdot blame [options] TARGET",
+                        "       xdot explain [options] PATH",
+                        "       xdot state export [-o FILE] [options]",
+                        "       xdot state diff [options] FILE",
+                        "       xdot new [--template NAME] [options] PACKAGE",
+                        "       xdot adopt [options] PACKAGE PATH...",
+                        "       xdot completions [--install] [options] [bash|zsh|fish]",
                         "Symlink your dotfiles from `~/.xdot`.",
                         "",
+                        "Commands:",
+                        "  scan           Offer to adopt well-known dotfiles found in `$HOME`.",
+                        "  diff --vcs     Show uncommitted changes to linked files.",
+                        "  githook        Relink all packages after `git pull` or `git checkout`.",
+                        "  graph          Print a Graphviz (or mermaid) graph of packages and targets.",
+                        "  report         Write an HTML summary of every package's link status.",
+                        "  freeze         Replace symlinks with copies of the files they point to.",
+                        "  thaw           Replace unmodified copies with symlinks again.",
+                        "  deploy         Copy packages to HOST over SSH and link them there.",
+                        "  export FORMAT  Generate a shell script, Ansible playbook or tmpfiles.d config.",
+                        "  targets        List the directories each package links into.",
+                        "  status         Count linked, missing and conflicting files per directory.",
+                        "  check          Fail if any file isn't linked, `--summary` counts per package.",
+                        "  prune          Remove broken symlinks into packages (e.g. to deleted files).",
+                        "  restow         Prune, then link packages again (e.g. after moving files).",
+                        "  orphans        List unmanaged files in directories packages link file by file.",
+                        "  backups        List the backups made with `--backup`, `-v` lists their files,",
+                        "                 `--prune` removes all but the last N or those older than DAYS.",
+                        "  restore        Move the files of backup ID back into place.",
+                        "  retry          Re-attempt the operations which failed with `--keep-going`.",
+                        "  doctor         Diagnose the setup, `--security` flags exposed secrets.",
+                        "  verify-repo    Lint the layout of the packages directory (e.g. in CI).",
+                        "  list           List packages, `--long` adds the summary of their README.md.",
+                        "  search         List packages whose name or README.md contains TERM.",
+                        "  blame          Show the package, source and last commit of TARGET.",
+                        "  explain        Show how PATH, a target or a package file, is handled.",
+                        "  state          Export the state of every target, or diff it with FILE's.",
+                        "  new            Create PACKAGE, `--template` copies a template from the registry.",
+                        "  adopt          Move existing files into PACKAGE, then link them back.",
+                        "  completions    Print (or install) the completion script of a shell.",
+                        "  <name>         Run `xdot-<name>` from `$PATH`, unless a package is named so.",
+                        "",
                         "Options:",
                         "  --all          Symlink all packages.",
+                        "  --hidden-packages",
+                        "                 Include packages whose name starts with `.` in `--all`.",
+                        "  --dir DIR      Look for packages in DIR, defaults to `$XDOT_DIR`, then",
+                        "                 `packages_root` in `~/.config/xdot/config.toml`, then `~/.xdot`.",
+                        "  --target DIR   Link into DIR instead of `$HOME` and `/`.",
+                        "  --layout LAYOUT",
+                        "                 Link the entries of packages which aren't `@`-prefixed relative",
+                        "                 to `/` (`root`, the default), `$HOME` (`home`) or the parent of",
+                        "                 the packages root (`stow`).",
+                        "  --env VAR=VALUE",
+                        "                 Act as if the environment variable VAR was VALUE, repeatable.",
                         "  --unlink       Remove symlinks.",
+                        "  --interactive  Ask how to resolve each conflict, with `--unlink` pick which",
+                        "                 symlinks to remove.",
+                        "  --yes          Answer yes instead of prompting: adopt everything `xdot scan`",
+                        "                 finds, unlink every symlink with `--interactive`, fail on",
+                        "                 conflicts.",
+                        "  --no-input     Take the default answer instead of prompting: adopt and",
+                        "                 unlink nothing, fail on conflicts.",
                         "  --dry-run      Don't modify the file system.",
+                        "  --quick        Only print something when a change is made.",
+                        "  --keep-broken  Treat broken symlinks as conflicts instead of replacing them.",
+                        "  --force        Replace existing regular files with links.",
+                        "  --backup[=DIR] Move replaced files into DIR rather than deleting them,",
+                        "                 defaults to `$XDG_STATE_HOME/xdot/backups`.",
+                        "  --dangling-sources POLICY",
+                        "                 Skip package files which are broken or unreadable with a",
+                        "                 warning (`warn`), fail (`error`) or link them (`link`).",
+                        "  --output sh    Print the operations as shell commands, don't apply them.",
+                        "  --keep-going   Carry on after a failure, for `xdot retry` to re-attempt.",
+                        "  --retries N    Retry operations failing with the I/O errors of network",
+                        "                 filesystems N times (3 by default), waiting longer each time.",
+                        "  --allow-foreign-dirs",
+                        "                 Operate on target directories owned by another user.",
+                        "  --follow-dir-symlinks",
+                        "                 Descend into target directories which are symlinks.",
+                        "  --relative     Create relative symlinks.",
+                        "  --hardlink     Create hard links, and directories, instead of symlinks.",
+                        "  --no-folding   Create directories rather than symlinking them, unfolding",
+                        "                 those already symlinked, so that every file has its own link.",
+                        "  --include-vcs  Link `.git`, `.hg` and `.svn` directories found in packages.",
+                        "  --conflicts-report FILE",
+                        "                 Write all conflicts to FILE as JSON rather than stopping.",
+                        "  --snapshot CMD Run CMD (e.g. a btrfs or ZFS snapshot) before any change.",
+                        "  --notify[=CMD] Once a run changed targets, run CMD with them as arguments,",
+                        "                 or show them with `notify-send`.",
+                        "  --umask MODE   Create files and directories with the umask MODE (e.g. `022`),",
+                        "                 which also restricts the permissions of copies.",
+                        "  --only GLOB    Only link package files matching GLOB, relative to their",
+                        "                 package (e.g. `@XDG_CONFIG_HOME/nvim/**`), repeatable.",
+                        "  --exclude GLOB Leave out package files matching GLOB, repeatable.",
+                        "  --filter EXPR  Only apply operations matching EXPR, e.g.",
+                        "                 `target ~ \"\\.config/nvim\" && action == link`.",
                         "  -v, --verbose  Increase verbosity.",
                         "  -h, --help     Show this help message and exit.",
                         "  --version      Show version information and exit.",
@@ -94,16 +732,150 @@ impl Args {
 
                     std::process::exit(0);
                 }
-                Arg::Value(package) => match args.package_spec {
-                    PackageSpec::All => {
-                        bail!("Cannot specify packages after `--all`");
+                Arg::Long("vcs") if matches!(args.command, Command::Diff) => {
+                    args.options.vcs = true;
+                }
+                Arg::Long("mermaid") if matches!(args.command, Command::Graph) => {
+                    args.options.mermaid = true;
+                }
+                Arg::Short('o')
+                    if matches!(
+                        args.command,
+                        Command::Report | Command::Export(_) | Command::State(_)
+                    ) =>
+                {
+                    args.options.output = Some(parser.value()?.into());
+                }
+                Arg::Value(action) if matches!(args.command, Command::GitHook(None)) => {
+                    args.command = Command::GitHook(Some(action.parse()?));
+                }
+                Arg::Long("all-hosts") if matches!(args.command, Command::Deploy(None)) => {
+                    args.options.all_hosts = true;
+                }
+                Arg::Long("remote-dir") if matches!(args.command, Command::Deploy(_)) => {
+                    args.options.remote_dir = Some(parser.value()?);
+                }
+                Arg::Long("host") if matches!(args.command, Command::Status) => {
+                    args.options.host = Some(parser.value()?);
+                }
+                Arg::Value(host)
+                    if matches!(args.command, Command::Deploy(None)) && !args.options.all_hosts =>
+                {
+                    args.command = Command::Deploy(Some(host));
+                }
+                Arg::Value(format) if matches!(args.command, Command::Export(None)) => {
+                    args.command = Command::Export(Some(format.parse()?));
+                }
+                Arg::Value(shell) if matches!(args.command, Command::Completions(None)) => {
+                    args.command = Command::Completions(Some(shell.parse()?));
+                }
+                Arg::Long("keep-going")
+                    if matches!(args.command, Command::Link | Command::Restow) =>
+                {
+                    args.options.keep_going = true;
+                }
+                Arg::Long("retries")
+                    if matches!(
+                        args.command,
+                        Command::Link | Command::Restow | Command::Retry
+                    ) =>
+                {
+                    args.options.retries = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("allow-foreign-dirs")
+                    if matches!(
+                        args.command,
+                        Command::Link | Command::Restow | Command::Retry
+                    ) =>
+                {
+                    args.options.allow_foreign_dirs = true;
+                }
+                Arg::Long("summary") if matches!(args.command, Command::Check) => {
+                    args.options.summary = true;
+                }
+                Arg::Long("security") if matches!(args.command, Command::Doctor) => {
+                    args.options.security = true;
+                }
+                Arg::Long("long") | Arg::Short('l') if matches!(args.command, Command::List) => {
+                    args.options.long = true;
+                }
+                Arg::Value(term) if matches!(args.command, Command::Search(None)) => {
+                    args.command = Command::Search(Some(term.string()?));
+                }
+                Arg::Value(id) if matches!(args.command, Command::Restore(None)) => {
+                    args.command = Command::Restore(Some(id));
+                }
+                Arg::Value(target) if matches!(args.command, Command::Blame(None)) => {
+                    args.command = Command::Blame(Some(target.into()));
+                }
+                Arg::Value(path) if matches!(args.command, Command::Explain(None)) => {
+                    args.command = Command::Explain(Some(path.into()));
+                }
+                Arg::Value(action) if matches!(args.command, Command::State(None)) => {
+                    args.command = Command::State(Some(action.parse()?));
+                }
+                Arg::Value(path)
+                    if matches!(
+                        args.command,
+                        Command::State(Some(state::Action::Diff(None)))
+                    ) =>
+                {
+                    args.command = Command::State(Some(state::Action::Diff(Some(path.into()))));
+                }
+                Arg::Value(package) if matches!(args.command, Command::New(None)) => {
+                    args.command = Command::New(Some(package));
+                }
+                Arg::Value(value) if matches!(args.command, Command::Adopt { .. }) => {
+                    if let Command::Adopt { package, paths } = &mut args.command {
+                        if package.is_none() {
+                            *package = Some(value);
+                        } else {
+                            paths.push(value.into());
+                        }
                     }
-                    PackageSpec::None => {
-                        args.package_spec = PackageSpec::List(vec![package.into_boxed_os_str()]);
+                }
+                Arg::Long("template") if matches!(args.command, Command::New(_)) => {
+                    args.options.template = Some(parser.value()?.string()?);
+                }
+                Arg::Long("files") if matches!(args.command, Command::Bench) => {
+                    args.options.files = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("depth") if matches!(args.command, Command::Bench) => {
+                    args.options.depth = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("prune") if matches!(args.command, Command::Backups) => {
+                    args.options.prune = true;
+                }
+                Arg::Long("keep") if matches!(args.command, Command::Backups) => {
+                    args.options.keep = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("max-age") if matches!(args.command, Command::Backups) => {
+                    args.options.max_age = Some(parser.value()?.parse()?);
+                }
+                Arg::Long("install") if matches!(args.command, Command::Completions(_)) => {
+                    args.options.install = true;
+                }
+                Arg::Value(_) if !args.command.takes_packages() => {
+                    bail!(arg.unexpected());
+                }
+                Arg::Value(package) => {
+                    let packages = if package == "-" {
+                        read_package_list(std::io::stdin().lock())?
+                    } else {
+                        vec![package.into_boxed_os_str()]
+                    };
+
+                    match args.package_spec {
+                        PackageSpec::All => {
+                            bail!("Cannot specify packages after `--all`");
+                        }
+                        PackageSpec::None => {
+                            args.package_spec = PackageSpec::List(packages);
+                        }
+                        PackageSpec::List(ref mut list) => list.extend(packages),
                     }
-                    PackageSpec::List(ref mut list) => list.push(package.into_boxed_os_str()),
-                },
-                Arg::Long("all") => {
+                }
+                Arg::Long("all") if args.command.takes_packages() => {
                     if let PackageSpec::List(_) = args.package_spec {
                         bail!("Cannot specify `--all` after explicit packages");
                     }
@@ -118,190 +890,2523 @@ impl Args {
     }
 }
 
-fn main() -> Result<()> {
-    let home = match std::env::var_os("HOME") {
-        Some(home) => PathBuf::from(home).into_boxed_path(),
-        None => bail!("$HOME is not set"),
-    };
+/// Reads package names separated by newlines, or by NUL bytes if there are any.
+fn read_package_list(mut reader: impl std::io::Read) -> Result<Vec<Box<OsStr>>> {
+    let mut input = Vec::new();
 
-    let Args {
-        package_spec,
-        options,
-    } = Args::from_env()?;
+    reader
+        .read_to_end(&mut input)
+        .context("Unable to read package list")?;
 
-    if matches!(package_spec, PackageSpec::None) {
-        bail!("No packages specified");
+    let separator = if input.contains(&b'\0') { b'\0' } else { b'\n' };
+
+    Ok(input
+        .split(|byte| *byte == separator)
+        .map(|name| name.strip_suffix(b"\r").unwrap_or(name))
+        .filter(|name| !name.is_empty())
+        .map(|name| OsStr::from_bytes(name).into())
+        .collect())
+}
+
+/// Paths shared by every command.
+struct Context {
+    home: Box<Path>,
+    /// `$XDG_CONFIG_HOME/xdot/config.toml`, which may not exist.
+    config_path: Box<Path>,
+    packages_root: Box<Path>,
+    /// Where `packages_root` comes from, e.g. `XDOT_DIR`.
+    packages_root_origin: &'static str,
+    /// Replaces `$HOME` and `/` as the destination of links, with `--target`.
+    target: Option<Box<Path>>,
+    layout: Layout,
+    /// Environment variables overridden with `--env`.
+    env: BTreeMap<OsString, OsString>,
+    /// Defaults of `@` variables from `vars.toml`, relative to `$HOME` unless absolute.
+    var_defaults: BTreeMap<String, PathBuf>,
+    /// `@` variables of this machine from `config.toml`, see [`Context::expand`].
+    var_mappings: BTreeMap<String, VarMapping>,
+    /// The template registry of `xdot new`, see [`new`].
+    templates: Option<String>,
+    /// Guards against linking, e.g., a build directory dropped into a package by mistake.
+    max_files_per_package: usize,
+    max_descend_depth: usize,
+    /// Whether `--all` includes packages whose name starts with `.`.
+    hidden_packages: bool,
+    /// The configuration files of the packages walked so far, by package path, see
+    /// [`Context::package_config`].
+    package_configs: RefCell<HashMap<PathBuf, Rc<PackageConfig>>>,
+}
+
+/// The files configuring a package, read once it is walked so that an invalid one only fails the
+/// commands using that package.
+struct PackageConfig {
+    /// Package files linked elsewhere, and their target, from `links.toml`.
+    links: BTreeMap<PathBuf, String>,
+    /// Package files and directories, and their mode, from `modes.toml`.
+    modes: BTreeMap<PathBuf, u32>,
+}
+
+const DEFAULT_MAX_FILES_PER_PACKAGE: usize = 10_000;
+const DEFAULT_MAX_DESCEND_DEPTH: usize = 32;
+
+/// `$XDG_CONFIG_HOME/xdot/config.toml`.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Relative to `$HOME` unless absolute.
+    packages_root: Option<PathBuf>,
+    /// Default of `--follow-dir-symlinks`.
+    #[serde(default)]
+    follow_dir_symlinks: bool,
+    /// Default of `--hidden-packages`.
+    #[serde(default)]
+    hidden_packages: bool,
+    /// Patterns of package files not to link, with the syntax of `.gitignore` files, relative to
+    /// each package.
+    #[serde(default)]
+    ignore: Vec<String>,
+    /// Default of `--retries`.
+    retries: Option<u32>,
+    /// Default of `--umask`, in octal.
+    umask: Option<String>,
+    /// `@` variables of this machine, which take precedence over `vars.toml`.
+    #[serde(default)]
+    vars: BTreeMap<String, VarMapping>,
+    /// A directory, relative to `$HOME` unless absolute, or a git URL.
+    templates: Option<String>,
+    max_files_per_package: Option<usize>,
+    max_descend_depth: Option<usize>,
+    /// Default of `--layout`.
+    layout: Option<Layout>,
+}
+
+/// A value of `vars` in `config.toml`: a path, or paths to try in order.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VarMapping {
+    Path(String),
+    Fallbacks(Vec<String>),
+}
+
+impl VarMapping {
+    fn paths(&self) -> &[String] {
+        match self {
+            Self::Path(path) => std::slice::from_ref(path),
+            Self::Fallbacks(paths) => paths,
+        }
     }
+}
 
-    if options.dry_run {
-        println!("Dry run mode, no changes will be made.");
+/// `xdot.toml`, at the root of the packages directory.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PackagesConfig {
+    /// Patterns of package files to copy rather than link, with the syntax of `.gitignore` files,
+    /// relative to the packages root.
+    #[serde(default)]
+    copy: Vec<String>,
+    dot_prefix: Option<dot_prefix::Setting>,
+}
+
+impl Config {
+    /// Describes the fields above, keep both in sync.
+    fn json_schema() -> serde_json::Value {
+        serde_json::json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "xdot configuration",
+            "description": "`$XDG_CONFIG_HOME/xdot/config.toml`",
+            "type": "object",
+            "properties": {
+                "packages_root": {
+                    "description": "The packages root, relative to `$HOME` unless absolute.",
+                    "type": "string",
+                },
+                "follow_dir_symlinks": {
+                    "description": "Default of `--follow-dir-symlinks`.",
+                    "type": "boolean",
+                    "default": false,
+                },
+                "hidden_packages": {
+                    "description": "Default of `--hidden-packages`.",
+                    "type": "boolean",
+                    "default": false,
+                },
+                "ignore": {
+                    "description": "Patterns of package files not to link, with the syntax of `.gitignore` files, relative to each package.",
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "default": [],
+                },
+                "retries": {
+                    "description": "Default of `--retries`: how many times operations failing with the I/O errors of network filesystems are retried.",
+                    "type": "integer",
+                    "minimum": 0,
+                    "default": transient::DEFAULT_RETRIES,
+                },
+                "umask": {
+                    "description": "Default of `--umask`: the umask of runs, in octal, e.g. `022`.",
+                    "type": "string",
+                    "pattern": "^[0-7]{1,3}$",
+                },
+                "vars": {
+                    "description": "`@` variables of this machine, used when they aren't set: a path, or paths to try in order. `~` and `$VAR` are expanded, paths are relative to `$HOME` unless absolute.",
+                    "type": "object",
+                    "additionalProperties": {
+                        "anyOf": [
+                            { "type": "string" },
+                            { "type": "array", "items": { "type": "string" } },
+                        ],
+                    },
+                    "default": {},
+                },
+                "templates": {
+                    "description": "The template registry of `xdot new`: a directory, relative to `$HOME` unless absolute, or a git URL.",
+                    "type": "string",
+                },
+                "max_files_per_package": {
+                    "description": "The number of files a package may hold.",
+                    "type": "integer",
+                    "minimum": 0,
+                    "default": DEFAULT_MAX_FILES_PER_PACKAGE,
+                },
+                "max_descend_depth": {
+                    "description": "How deep a package may descend into existing directories.",
+                    "type": "integer",
+                    "minimum": 0,
+                    "default": DEFAULT_MAX_DESCEND_DEPTH,
+                },
+                "layout": {
+                    "description": "Default of `--layout`: how package entries which aren't `@`-prefixed map to their targets.",
+                    "enum": ["root", "home", "stow"],
+                    "default": "root",
+                },
+            },
+            "additionalProperties": false,
+        })
     }
+}
 
-    let default_xdg_data_home = home.join(".local/share").into_boxed_path();
-    let default_xdg_state_home = home.join(".local/state").into_boxed_path();
-    let default_xdg_cache_home = home.join(".cache").into_boxed_path();
-    let default_xdg_config_home = home.join(".config").into_boxed_path();
-
-    let packages_root = PathBuf::from_iter([&home, Path::new(".xdot")]).into_boxed_path();
-
-    let packages = match package_spec {
-        PackageSpec::None => unreachable!(),
-        PackageSpec::All => WalkBuilder::new(&packages_root)
-            .require_git(true)
-            .hidden(true)
-            .parents(true)
-            .ignore(true)
-            .git_global(true)
-            .git_ignore(true)
-            .git_exclude(true)
-            .max_depth(Some(1))
-            .follow_links(false)
-            .filter_entry(
-                |entry| matches!(entry.file_type(), Some(file_type) if file_type.is_dir()),
-            )
-            .build()
-            .skip(1)
-            .map(|entry| entry.map(|entry| entry.file_name().to_owned().into_boxed_os_str()))
-            .collect::<Result<Vec<_>, _>>()
-            .with_context(|| format!("Unable to list packages ({})", packages_root.display()))?
-            .into_boxed_slice(),
-        PackageSpec::List(list) => list.into_boxed_slice(),
-    };
+impl Context {
+    /// Finds the packages root in `--dir`, `XDOT_DIR`, the config file then defaults to
+    /// `~/.xdot`. Options of the config file are applied to `options`.
+    fn from_env(options: &mut Options) -> Result<Self> {
+        // Later `--env` flags take precedence.
+        let env = options.env.iter().cloned().collect::<BTreeMap<_, _>>();
 
-    for package in packages.iter() {
-        let package_path =
-            PathBuf::from_iter([&packages_root, Path::new(&package)]).into_boxed_path();
+        let var = |name: &str| {
+            env.get(OsStr::new(name))
+                .cloned()
+                .or_else(|| std::env::var_os(name))
+        };
 
-        println!(
-            "{} config for `{}` ({})",
-            if options.unlink {
-                "Unlinking"
-            } else {
-                "Linking"
+        let home = match var("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+            .or_else(ownership::home_dir)
+        {
+            Some(home) => home.into_boxed_path(),
+            None => bail!("$HOME is not set, nor is a home directory in the user database"),
+        };
+
+        let config_path = var("XDG_CONFIG_HOME")
+            .map_or_else(|| home.join(".config"), PathBuf::from)
+            .join("xdot/config.toml");
+
+        let config = read_toml::<Config>(&config_path)?.unwrap_or_default();
+
+        options.follow_dir_symlinks |= config.follow_dir_symlinks;
+        options.hidden_packages |= config.hidden_packages;
+        options.retries = options.retries.or(config.retries);
+
+        if let (None, Some(mode)) = (options.umask, &config.umask) {
+            options.umask = Some(umask::parse(mode).context("Invalid config.toml")?);
+        }
+
+        let (packages_root, packages_root_origin) = if let Some(dir) = &options.dir {
+            (dir.clone(), "--dir")
+        } else if let Some(dir) = var("XDOT_DIR").filter(|dir| !dir.is_empty()) {
+            (PathBuf::from(dir), "XDOT_DIR")
+        } else if let Some(dir) = config.packages_root {
+            (home.join(dir), "packages_root in config.toml")
+        } else {
+            (home.join(".xdot"), "default")
+        };
+
+        let packages_root = packages_root.into_boxed_path();
+
+        let var_defaults = read_toml(&packages_root.join("vars.toml"))?.unwrap_or_default();
+
+        options.subset = subset::build(&packages_root, &options.only, &options.exclude)?;
+
+        options.ignored = xdotignore::load(&packages_root, &config.ignore, &config_path)?;
+
+        let packages_config_path = packages_root.join("xdot.toml");
+
+        let packages_config =
+            read_toml::<PackagesConfig>(&packages_config_path)?.unwrap_or_default();
+
+        if let Some(setting) = &packages_config.dot_prefix {
+            options.dot_prefixed = dot_prefix::packages(&packages_root, setting);
+        }
+
+        if !packages_config.copy.is_empty() {
+            let mut copied = GitignoreBuilder::new(&packages_root);
+
+            for pattern in &packages_config.copy {
+                copied
+                    .add_line(Some(packages_config_path.clone()), pattern)
+                    .with_context(|| format!("Invalid pattern `{pattern}` in xdot.toml"))?;
+            }
+
+            options.copied = Some(copied.build().context("Invalid xdot.toml")?);
+        }
+
+        let target = match &options.target {
+            Some(target) => Some(
+                std::path::absolute(target)
+                    .with_context(|| format!("Invalid target {}", target.display()))?
+                    .into_boxed_path(),
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            home,
+            config_path: config_path.into_boxed_path(),
+            packages_root,
+            packages_root_origin,
+            target,
+            layout: options.layout.or(config.layout).unwrap_or_default(),
+            env,
+            var_defaults,
+            var_mappings: config.vars,
+            templates: config.templates,
+            max_files_per_package: config
+                .max_files_per_package
+                .unwrap_or(DEFAULT_MAX_FILES_PER_PACKAGE),
+            max_descend_depth: config
+                .max_descend_depth
+                .unwrap_or(DEFAULT_MAX_DESCEND_DEPTH),
+            hidden_packages: options.hidden_packages,
+            package_configs: RefCell::default(),
+        })
+    }
+
+    /// Returns the configuration files of the package at `package_path`, read the first time.
+    fn package_config(&self, package_path: &Path) -> Result<Rc<PackageConfig>> {
+        if let Some(config) = self.package_configs.borrow().get(package_path) {
+            return Ok(Rc::clone(config));
+        }
+
+        let config = Rc::new(PackageConfig {
+            links: links::read(package_path)?,
+            modes: modes::read(package_path)?,
+        });
+
+        self.package_configs
+            .borrow_mut()
+            .insert(package_path.to_owned(), Rc::clone(&config));
+
+        Ok(config)
+    }
+
+    /// Returns the configuration files of the package holding the package file `original`,
+    /// unless they are invalid, in which case walking the package reports it.
+    fn package_config_of(&self, original: &Path) -> Option<Rc<PackageConfig>> {
+        let package = original
+            .strip_prefix(&self.packages_root)
+            .ok()?
+            .components()
+            .next()?;
+
+        self.package_config(&self.packages_root.join(package)).ok()
+    }
+
+    /// Returns the directory package entries which aren't `@`-prefixed are linked into, see
+    /// [`Layout`].
+    fn layout_root(&self) -> PathBuf {
+        match self.layout {
+            Layout::Root => self.target.as_deref().unwrap_or(Path::new("/")).to_owned(),
+            Layout::Home => self.retarget(self.home.to_path_buf()),
+            Layout::Stow => match &self.target {
+                Some(target) => target.to_path_buf(),
+                None => std::path::absolute(&self.packages_root)
+                    .ok()
+                    .and_then(|root| Some(root.parent()?.to_owned()))
+                    .unwrap_or_else(|| PathBuf::from("/")),
             },
-            package.to_string_lossy(),
-            package_path.display()
-        );
+        }
+    }
 
-        for original in package_path
-            .read_dir()
-            .context("Unable to read package content")?
-        {
-            let original = original?;
+    /// Resolves the directory designated by an `@`-prefixed package entry.
+    ///
+    /// Environment variables (or their `--env` overrides) take precedence, then `vars` of
+    /// `config.toml`, then defaults from `vars.toml`, XDG Base Directory variables fall back to
+    /// their spec compliant defaults. With `--target`, the result is moved into the target.
+    fn resolve_var(&self, name: &OsStr) -> Option<PathBuf> {
+        Some(self.retarget(self.lookup_var(name, 0)?))
+    }
 
-            if let Some(env_var_name) = strip_at_sign_prefix(&original.file_name()) {
-                let link = std::env::var_os(env_var_name).map(PathBuf::from);
+    /// Like [`Context::resolve_var`], without `--target`. `depth` counts the mappings of
+    /// `config.toml` being expanded, to stop at cycles.
+    fn lookup_var(&self, name: &OsStr, depth: usize) -> Option<PathBuf> {
+        let mapping = name.to_str().and_then(|name| self.var_mappings.get(name));
 
-                let link = link
-                    .as_deref()
-                    .or_else(|| match env_var_name.to_str() {
-                        Some("XDG_DATA_HOME") => Some(&*default_xdg_data_home),
-                        Some("XDG_STATE_HOME") => Some(&*default_xdg_state_home),
-                        Some("XDG_CACHE_HOME") => Some(&*default_xdg_cache_home),
-                        Some("XDG_CONFIG_HOME") => Some(&*default_xdg_config_home),
-                        _ => None,
-                    })
-                    .ok_or_else(|| {
-                        anyhow!(
-                            "Unable to find environment variable `{}`",
-                            env_var_name.to_string_lossy()
-                        )
-                    })?;
-
-                descend_and_symlink(&original.path(), link, &options)?;
+        Some(
+            if let Some(value) = self
+                .env
+                .get(name)
+                .cloned()
+                .or_else(|| std::env::var_os(name))
+            {
+                PathBuf::from(value)
+            } else if let Some(mapping) = mapping.filter(|_| depth < MAX_MAPPING_DEPTH) {
+                mapping
+                    .paths()
+                    .iter()
+                    .find_map(|path| self.expand(path, depth + 1))?
+            } else if let Some(default) = self.default_var(name) {
+                self.home.join(default)
+            } else if name == "HOME" {
+                self.home.to_path_buf()
+            } else if name == "XDG_RUNTIME_DIR" {
+                default_runtime_dir()?
+            } else if name == wsl::WINHOME {
+                wsl::windows_home()?
             } else {
-                symlink_or_descend(
-                    &original.path(),
-                    &PathBuf::from_iter([
-                        Path::new("/"),
-                        original.path().strip_prefix(&package_path)?,
-                    ]),
-                    &options,
-                )?;
+                return None;
+            },
+        )
+    }
+
+    /// Expands a path of `vars` in `config.toml`: a leading `~`, then `$VAR` and `${VAR}` (any
+    /// `@` variable), relative to `$HOME` unless absolute. Fails if a variable can't be resolved,
+    /// for the next fallback to be tried.
+    fn expand(&self, path: &str, depth: usize) -> Option<PathBuf> {
+        let mut expanded = String::new();
+
+        let rest = match path.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                expanded.push_str(self.home.to_str()?);
+
+                rest
+            }
+            _ => path,
+        };
+
+        let mut chars = rest.chars().peekable();
+
+        while let Some(char) = chars.next() {
+            if char != '$' {
+                expanded.push(char);
+
+                continue;
+            }
+
+            let braced = chars.next_if_eq(&'{').is_some();
+            let mut name = String::new();
+
+            while let Some(char) =
+                chars.next_if(|char| char.is_ascii_alphanumeric() || *char == '_')
+            {
+                name.push(char);
+            }
+
+            if name.is_empty() || (braced && chars.next() != Some('}')) {
+                return None;
             }
+
+            expanded.push_str(self.lookup_var(OsStr::new(&name), depth)?.to_str()?);
         }
+
+        Some(self.home.join(expanded))
     }
 
-    Ok(())
-}
+    /// Moves `path` into the `--target` directory, if any: paths inside `$HOME` are made relative
+    /// to it, other paths relative to `/`.
+    fn retarget(&self, path: PathBuf) -> PathBuf {
+        let Some(target) = &self.target else {
+            return path;
+        };
 
-/// Returns a substring with the `U+0040 AT SIGN (@)` prefix removed.
-///
-/// If the string starts with a `U+0040 AT SIGN (@)`, returns substring after the prefix, wrapped
-/// in `Some`. The prefix is removed exactly once.
-///
-/// If the string does not start with a `U+0040 AT SIGN (@)`, returns `None`.
-fn strip_at_sign_prefix(file_name: &OsStr) -> Option<&OsStr> {
-    let file_name = file_name.as_bytes();
+        match path.strip_prefix(&self.home) {
+            Ok(relative) => target.join(relative),
+            Err(_) => target.join(path.strip_prefix("/").unwrap_or(&path)),
+        }
+    }
 
-    if file_name[0] == b'@' {
-        Some(OsStr::from_bytes(&file_name[1..]))
-    } else {
-        None
+    /// Returns the default value of an environment variable, relative to `$HOME` unless absolute.
+    fn default_var(&self, name: &OsStr) -> Option<&Path> {
+        let name = name.to_str()?;
+
+        self.var_defaults
+            .get(name)
+            .map(PathBuf::as_path)
+            .or_else(|| {
+                builtin_vars()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, default)| Path::new(default))
+            })
     }
-}
 
-/// Symlink the children of `original` to the children of `link`.
-fn descend_and_symlink(original: &Path, link: &Path, options: &Options) -> Result<()> {
-    for entry in original
-        .read_dir()
-        .with_context(|| format!("Unable to descend into {}", original.display()))?
-    {
-        let entry = entry?;
+    /// Lists the packages matching `package_spec`.
+    fn packages(&self, package_spec: PackageSpec) -> Result<Box<[Box<OsStr>]>> {
+        let packages_root = &self.packages_root;
 
-        symlink_or_descend(&entry.path(), &link.join(entry.file_name()), options)?;
+        Ok(match package_spec {
+            PackageSpec::None => bail!("No packages specified"),
+            PackageSpec::All => WalkBuilder::new(packages_root)
+                .require_git(true)
+                .hidden(!self.hidden_packages)
+                .parents(true)
+                .ignore(true)
+                .git_global(true)
+                .git_ignore(true)
+                .git_exclude(true)
+                .add_custom_ignore_filename(xdotignore::FILE)
+                .max_depth(Some(1))
+                .follow_links(false)
+                .filter_entry(|entry| {
+                    matches!(entry.file_type(), Some(file_type) if file_type.is_dir())
+                        && !is_vcs_dir(entry.file_name())
+                })
+                .build()
+                .skip(1)
+                .map(|entry| entry.map(|entry| entry.file_name().to_owned().into_boxed_os_str()))
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Unable to list packages ({})", packages_root.display()))?
+                .into_boxed_slice(),
+            PackageSpec::List(list) => list.into_boxed_slice(),
+        })
     }
 
-    Ok(())
-}
+    /// Fails with every `@`-prefixed entry of `packages` which can't be resolved, so that they can
+    /// all be fixed at once.
+    fn check_vars(&self, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+        let mut unresolved = BTreeMap::<OsString, Vec<String>>::new();
 
-/// Symlink `original` to `link`, or, if `original` already exists and is a directory, calls [`descend_and_symlink`].
-fn symlink_or_descend(original: &Path, link: &Path, options: &Options) -> Result<()> {
-    match (link.metadata(), original.metadata()) {
-        (Ok(a), Ok(b)) if a.ino() == b.ino() && a.dev() == b.dev() => {
-            if options.unlink {
-                println!("Removing symlink: {}", link.display());
+        for package in packages {
+            let package_path = self.package_path(package);
 
-                if !options.dry_run {
-                    std::fs::remove_file(link).context("Unable to remove symlink")?;
+            // Unreadable directories are reported when linking.
+            for var_dir in var_dirs(self, &package_path, options) {
+                if let Some(name) = var_dir.file_name().and_then(strip_at_sign_prefix) {
+                    match self.resolve_var(name) {
+                        None => {
+                            let users = unresolved.entry(name.to_owned()).or_default();
+                            let user = format!("`{}`", package.to_string_lossy());
+
+                            // The same variable may be used at several depths.
+                            if !users.contains(&user) {
+                                users.push(user);
+                            }
+                        }
+                        // Likely a session without login (cron, `su`), where the directory is
+                        // another session's and is emptied once that one ends.
+                        Some(directory)
+                            if name == "XDG_RUNTIME_DIR"
+                                && self.default_var(name).is_none()
+                                && !self.var_mappings.contains_key("XDG_RUNTIME_DIR")
+                                && !self.env.contains_key(name)
+                                && std::env::var_os(name).is_none() =>
+                        {
+                            eprintln!(
+                                "Warning: `XDG_RUNTIME_DIR` isn't set, `{}` links into {}, which \
+                                is emptied at logout",
+                                package.to_string_lossy(),
+                                directory.display()
+                            );
+                        }
+                        Some(_) => {}
+                    }
                 }
-            } else if options.verbosity > 0 {
-                println!("Skipping preexisting symlink: {}", link.display());
             }
+        }
 
-            Ok(())
+        if unresolved.is_empty() {
+            return Ok(());
         }
-        (Ok(link_metadata), _) => {
-            if link_metadata.is_file() {
-                bail!("{} already exists", link.display());
-            }
 
-            if options.verbosity > 0 {
-                println!("Descending into preexisting directory: {}", link.display());
-            }
+        let mut message = String::from("Unable to resolve the following `@` directories:");
+
+        for (name, packages) in &unresolved {
+            write!(
+                message,
+                "\n  @{} (used by {})",
+                name.to_string_lossy(),
+                packages.join(", ")
+            )?;
+        }
 
-            descend_and_symlink(original, link, options)?;
+        message.push_str(
+            "\nSet the environment variables, map them in config.toml or give them a default in vars.toml, current defaults:",
+        );
 
-            Ok(())
+        for (name, mapping) in &self.var_mappings {
+            write!(
+                message,
+                "\n  {name:<16} {} (config.toml)",
+                mapping.paths().join(", then ")
+            )?;
         }
-        _ => {
-            if !options.unlink {
-                println!("{} => {}", link.display(), original.display());
 
-                if !options.dry_run {
-                    symlink(original, link).with_context(|| {
-                        format!(
-                            "Unable to symlink {} => {}",
-                            link.display(),
-                            original.display()
-                        )
-                    })?;
+        for (name, default) in &self.var_defaults {
+            write!(
+                message,
+                "\n  {name:<16} {} (vars.toml)",
+                self.home.join(default).display()
+            )?;
+        }
+
+        for (name, default) in builtin_vars() {
+            if !self.var_defaults.contains_key(*name) {
+                if default.starts_with('/') {
+                    write!(message, "\n  {name:<16} {default}")?;
+                } else {
+                    write!(message, "\n  {name:<16} $HOME/{default}")?;
                 }
-            } else if options.verbosity > 0 {
-                println!("Skipping non-existent file: {}", link.display());
             }
+        }
 
-            Ok(())
+        if !builtin_vars().any(|(name, _)| *name == "XDG_RUNTIME_DIR")
+            && !self.var_defaults.contains_key("XDG_RUNTIME_DIR")
+        {
+            write!(
+                message,
+                "\n  {:<16} /run/user/UID, if it exists and is only accessible to you",
+                "XDG_RUNTIME_DIR"
+            )?;
         }
+
+        bail!(message)
     }
+
+    /// Fails if a package of `packages` has more files, or deeper ones, than allowed by
+    /// `config.toml`.
+    fn check_limits(&self, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+        for package in packages {
+            let package_path = self.package_path(package);
+
+            let mut files = 0;
+
+            for (entry, link) in self.package_entries(&package_path, options)? {
+                walk_readable_files(self, &entry, &link, options, &mut |original, _| {
+                    files += 1;
+
+                    if files > self.max_files_per_package {
+                        bail!(
+                            "`{}` has more than {} files, which looks like a mistake (e.g. a \
+                            build directory); raise `max_files_per_package` in config.toml if \
+                            it isn't",
+                            package.to_string_lossy(),
+                            self.max_files_per_package
+                        );
+                    }
+
+                    // Directories between the top-level entry and the file.
+                    let depth = original
+                        .strip_prefix(&entry)?
+                        .components()
+                        .count()
+                        .saturating_sub(1);
+
+                    if depth > self.max_descend_depth {
+                        bail!(
+                            "{} is nested {depth} directories deep, more than {}; raise \
+                            `max_descend_depth` in config.toml if this is expected",
+                            original.display(),
+                            self.max_descend_depth
+                        );
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails if a link of `packages` would be created inside the packages root, which would
+    /// create recursive structures.
+    fn check_targets(&self, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+        // The packages root itself may be a symlink.
+        let roots = [
+            Some(self.packages_root.to_path_buf()),
+            self.packages_root.canonicalize().ok(),
+        ];
+
+        let is_inside_root = |link: &Path| {
+            let canonical = link
+                .parent()
+                .and_then(|parent| parent.canonicalize().ok())
+                .map(|parent| parent.join(link.file_name().unwrap_or_default()));
+
+            roots.iter().flatten().any(|root| {
+                link.starts_with(root)
+                    || canonical
+                        .as_ref()
+                        .is_some_and(|link| link.starts_with(root))
+            })
+        };
+
+        for package in packages {
+            for (original, link) in self.package_entries(&self.package_path(package), options)? {
+                // Descending into an ancestor of the packages root, or through symlinks, may reach
+                // it.
+                let is_ancestor = roots.iter().flatten().any(|root| root.starts_with(&link));
+
+                if !is_inside_root(&link) && !is_ancestor && !options.follow_dir_symlinks {
+                    continue;
+                }
+
+                walk_readable_files(self, &original, &link, options, &mut |original, link| {
+                    if is_inside_root(link) {
+                        bail!(
+                            "Refusing to link {} => {}: the target is inside the packages root ({}), \
+                            which would create a recursive structure. Check the `@` directories of \
+                            `{}` and the variables they designate.",
+                            link.display(),
+                            original.display(),
+                            self.packages_root.display(),
+                            package.to_string_lossy()
+                        );
+                    }
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails if package files of `packages` have the same target, or targets inside one another,
+    /// listing every collision before anything is linked.
+    fn check_collisions(&self, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+        let mut sources = BTreeMap::<PathBuf, Vec<(&OsStr, PathBuf)>>::new();
+
+        for package in packages {
+            for (entry, link) in self.package_entries(&self.package_path(package), options)? {
+                walk_readable_files(self, &entry, &link, options, &mut |original, link| {
+                    sources
+                        .entry(link.to_owned())
+                        .or_default()
+                        .push((package, original.to_owned()));
+
+                    Ok(())
+                })?;
+            }
+        }
+
+        let mut collisions = String::new();
+
+        for (link, providers) in &sources {
+            // Descendants of `link` come right after it.
+            let below = sources
+                .range::<PathBuf, _>((Bound::Excluded(link), Bound::Unbounded))
+                .take_while(|(other, _)| other.starts_with(link))
+                .flat_map(|(_, providers)| providers);
+
+            let providers = providers.iter().chain(below).collect::<Vec<_>>();
+
+            if providers.len() < 2 {
+                continue;
+            }
+
+            let _ = write!(collisions, "\n  {}", link.display());
+
+            for (package, original) in providers {
+                let _ = write!(
+                    collisions,
+                    "\n    `{}`: {}",
+                    package.to_string_lossy(),
+                    original.display()
+                );
+            }
+        }
+
+        if !collisions.is_empty() {
+            bail!("Several package files have the same target, nothing was linked:{collisions}");
+        }
+
+        Ok(())
+    }
+
+    fn package_path(&self, package: &OsStr) -> Box<Path> {
+        PathBuf::from_iter([&self.packages_root, Path::new(package)]).into_boxed_path()
+    }
+
+    /// Returns the top-level `(original, link)` pairs of a package.
+    ///
+    /// The content of `@`-prefixed directories is linked into the directory they designate,
+    /// everything else is linked relative to the root of the layout.
+    fn package_entries(
+        &self,
+        package_path: &Path,
+        options: &Options,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut entries = Vec::new();
+
+        self.collect_entries(
+            package_path,
+            &self.layout_root(),
+            true,
+            options,
+            &mut entries,
+        )?;
+
+        for (original, target) in &self.package_config(package_path)?.links {
+            entries.push((original.clone(), self.resolve_link_target(target)?));
+        }
+
+        Ok(entries)
+    }
+
+    /// Collects the `(original, link)` pairs of `directory`, the package itself (`is_package`) or
+    /// a directory organizing it by topic, which is linked at `link`.
+    ///
+    /// Directories holding `@`-prefixed directories at any depth, e.g. `work` in
+    /// `work/@XDG_CONFIG_HOME/git`, organize the package: they aren't linked themselves, their
+    /// entries are, see [`is_topic_dir`].
+    fn collect_entries(
+        &self,
+        directory: &Path,
+        link: &Path,
+        is_package: bool,
+        options: &Options,
+        entries: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<()> {
+        for original in directory
+            .read_dir()
+            .with_context(|| format!("Unable to read package content ({})", directory.display()))?
+        {
+            let original = original?;
+
+            if (is_package && is_package_metadata(&original.file_name()))
+                || is_excluded(self, &original.path(), options)
+            {
+                continue;
+            }
+
+            if let Some(env_var_name) = strip_at_sign_prefix(&original.file_name()) {
+                let link = self.resolve_var(env_var_name).ok_or_else(|| {
+                    anyhow!(
+                        "Unable to find environment variable `{}`",
+                        env_var_name.to_string_lossy()
+                    )
+                })?;
+
+                for entry in original.path().read_dir().with_context(|| {
+                    format!("Unable to descend into {}", original.path().display())
+                })? {
+                    let entry = entry?;
+
+                    if is_excluded(self, &entry.path(), options) {
+                        continue;
+                    }
+
+                    entries.push((
+                        entry.path(),
+                        link.join(dot_prefix::link_name(&entry.path(), options)),
+                    ));
+                }
+            } else if is_topic_dir(self, &original.path(), options) {
+                self.collect_entries(
+                    &original.path(),
+                    &link.join(entry_link_name(&original.path(), options)),
+                    false,
+                    options,
+                    entries,
+                )?;
+            } else {
+                entries.push((
+                    original.path(),
+                    link.join(entry_link_name(&original.path(), options)),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves a target of `links.toml`: `~/` and `@VAR/` prefixed paths are resolved like
+    /// `@HOME` and `@VAR` directories, relative ones are relative to the root of the layout.
+    fn resolve_link_target(&self, target: &str) -> Result<PathBuf> {
+        if let Some(rest) = target.strip_prefix("~/") {
+            return Ok(self.retarget(self.home.join(rest)));
+        }
+
+        if let Some(var) = target.strip_prefix('@') {
+            let (name, rest) = var.split_once('/').unwrap_or((var, ""));
+
+            return match self.resolve_var(OsStr::new(name)) {
+                Some(directory) => Ok(directory.join(rest)),
+                None => bail!("Unable to find environment variable `{name}`, used by `{target}`"),
+            };
+        }
+
+        if Path::new(target).is_absolute() {
+            return Ok(self.retarget(PathBuf::from(target)));
+        }
+
+        Ok(self.layout_root().join(target))
+    }
+}
+
+/// How deep mappings of `config.toml` may refer to each other.
+const MAX_MAPPING_DEPTH: usize = 8;
+
+/// Default values of environment variables, relative to `$HOME`.
+const DEFAULT_VARS: &[(&str, &str)] = &[
+    ("XDG_BIN_HOME", ".local/bin"),
+    ("XDG_DATA_HOME", ".local/share"),
+    ("XDG_STATE_HOME", ".local/state"),
+    ("XDG_CACHE_HOME", ".cache"),
+    ("XDG_CONFIG_HOME", ".config"),
+];
+
+/// Default values of environment variables on Termux, where there is no `/tmp` nor
+/// `/run/user`.
+const TERMUX_DEFAULT_VARS: &[(&str, &str)] = &[
+    ("PREFIX", "/data/data/com.termux/files/usr"),
+    ("TMPDIR", "/data/data/com.termux/files/usr/tmp"),
+    ("XDG_RUNTIME_DIR", "/data/data/com.termux/files/usr/var/run"),
+];
+
+/// Returns `/run/user/UID`, the runtime directory created by systemd-logind, as the default of
+/// `XDG_RUNTIME_DIR`. It is refused unless it is a directory owned by the user and only accessible
+/// to them, as the XDG Base Directory specification requires.
+fn default_runtime_dir() -> Option<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let path = PathBuf::from(format!("/run/user/{uid}"));
+    let metadata = path.metadata().ok()?;
+
+    (metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0).then_some(path)
+}
+
+/// Returns the built-in defaults of environment variables for this system.
+fn builtin_vars() -> impl Iterator<Item = &'static (&'static str, &'static str)> {
+    let termux = std::env::var_os("TERMUX_VERSION").is_some();
+
+    TERMUX_DEFAULT_VARS
+        .iter()
+        .filter(move |_| termux)
+        .chain(DEFAULT_VARS)
+}
+
+/// Reads and parses a TOML file, if it exists.
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error).with_context(|| format!("Unable to read {}", path.display()))
+        }
+    };
+
+    toml::from_str(&content)
+        .map(Some)
+        .with_context(|| format!("Invalid {}", path.display()))
+}
+
+/// Like [`walk_files`], but leaves out what can't be read, linking reports it.
+fn walk_readable_files(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    f: &mut dyn FnMut(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    let Ok(metadata) = original.symlink_metadata() else {
+        return Ok(());
+    };
+
+    walk_readable_entries(context, original, link, metadata.is_dir(), options, f)
+}
+
+/// [`walk_readable_files`] once the type of `original` is known, from its directory entry for
+/// the files below the package entry, which spares stating each of them.
+fn walk_readable_entries(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    is_dir: bool,
+    options: &Options,
+    f: &mut dyn FnMut(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    if !is_dir {
+        return f(original, link);
+    }
+
+    let Ok(entries) = original.read_dir() else {
+        return Ok(());
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if is_vcs_dir(&entry.file_name()) || is_excluded(context, &path, options) {
+            continue;
+        }
+
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        walk_readable_entries(
+            context,
+            &path,
+            &link.join(dot_prefix::link_name(&path, options)),
+            file_type.is_dir(),
+            options,
+            f,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Calls `f` with every non-directory `original` under `original` and its would-be `link`.
+fn walk_files(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    f: &mut dyn FnMut(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    let is_dir = original.symlink_metadata()?.is_dir();
+
+    walk_entries(context, original, link, is_dir, options, f)
+}
+
+/// [`walk_files`] once the type of `original` is known, see [`walk_readable_entries`].
+fn walk_entries(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    is_dir: bool,
+    options: &Options,
+    f: &mut dyn FnMut(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    if !is_dir {
+        return f(original, link);
+    }
+
+    for entry in original
+        .read_dir()
+        .with_context(|| format!("Unable to descend into {}", original.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+
+        if is_vcs_dir(&entry.file_name()) || is_excluded(context, &path, options) {
+            continue;
+        }
+
+        walk_entries(
+            context,
+            &path,
+            &link.join(dot_prefix::link_name(&path, options)),
+            entry.file_type()?.is_dir(),
+            options,
+            f,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Returns why the package file `original` would result in a dangling link, if it is a broken
+/// symlink or an unreadable file.
+fn dangling_reason(original: &Path, file_type: std::fs::FileType) -> Option<String> {
+    if file_type.is_symlink() {
+        (!original.exists()).then(|| match original.read_link() {
+            Ok(target) => format!("broken symlink to {}", target.display()),
+            Err(error) => error.to_string(),
+        })
+    } else if file_type.is_file() {
+        std::fs::File::open(original)
+            .err()
+            .map(|error| error.to_string())
+    } else {
+        None
+    }
+}
+
+/// Skips the package file `original`, which is broken or can't be read, for the final summary;
+/// fails instead with `--dangling-sources error`.
+fn skip_unreadable(
+    original: &Path,
+    reason: String,
+    options: &Options,
+    skipped: &mut Vec<(PathBuf, String)>,
+) -> Result<()> {
+    if matches!(options.dangling_sources, DanglingPolicy::Error) {
+        bail!("{} is broken or unreadable: {reason}", original.display());
+    }
+
+    skip(original, SkipReason::Unreadable, options);
+
+    skipped.push((original.to_owned(), reason));
+
+    Ok(())
+}
+
+/// Warns about the package files which were skipped because they are broken or can't be read.
+fn report_skipped(skipped: &[(PathBuf, String)]) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Warning: skipped {} broken or unreadable package file(s):",
+        skipped.len()
+    );
+
+    for (original, reason) in skipped {
+        eprintln!("  {}: {reason}", original.display());
+    }
+}
+
+/// Returns whether the package file `original` is left out when walking the package, because
+/// `links.toml` links it elsewhere, it matches `.xdotignore` or `--only` and `--exclude` leave it
+/// out.
+fn is_excluded(context: &Context, original: &Path, options: &Options) -> bool {
+    links::is_mapped(context, original)
+        || xdotignore::is_ignored(original, options)
+        || subset::is_left_out(original, options)
+}
+
+/// Returns whether `name`, at the top level of a package, is a file describing the package rather
+/// than one to link.
+fn is_package_metadata(name: &OsStr) -> bool {
+    [list::README, links::FILE, modes::FILE, xdotignore::FILE]
+        .iter()
+        .any(|file| name == *file)
+}
+
+/// Returns the `@`-prefixed directories of the package directory `directory`, either directly in
+/// it or in the directories organizing it by topic, see [`Context::collect_entries`].
+fn var_dirs(context: &Context, directory: &Path, options: &Options) -> Vec<PathBuf> {
+    let mut found = at_sign_entries(context, directory, options);
+
+    found.retain(|path| path.file_name().and_then(strip_at_sign_prefix).is_some());
+
+    found
+}
+
+/// Returns whether the package directory `directory` organizes its package by topic: it holds
+/// `@`-prefixed directories, or names escaped with `@@`, at any depth.
+fn is_topic_dir(context: &Context, directory: &Path, options: &Options) -> bool {
+    !at_sign_entries(context, directory, options).is_empty()
+}
+
+/// Returns the `@`-prefixed directories and the entries named with `@@` below `directory`, up to
+/// the first `@`-prefixed directory of each branch.
+fn at_sign_entries(context: &Context, directory: &Path, options: &Options) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+
+    let Ok(entries) = directory.read_dir() else {
+        return found;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if is_excluded(context, &path, options) {
+            continue;
+        }
+
+        if strip_at_sign_prefix(&entry.file_name()).is_some() {
+            if path.is_dir() {
+                found.push(path);
+            }
+
+            continue;
+        }
+
+        if entry.file_name().as_bytes().starts_with(b"@@") {
+            found.push(path.clone());
+        }
+
+        // Symlinks to directories are linked as they are.
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            found.extend(at_sign_entries(context, &path, options));
+        }
+    }
+
+    found
+}
+
+/// Returns the name the entry `original` of a package, or of a directory organizing it by topic,
+/// is linked as: a leading `@@` stands for a literal `@`.
+fn entry_link_name<'a>(original: &'a Path, options: &Options) -> Cow<'a, OsStr> {
+    match original.file_name().unwrap_or_default().as_bytes() {
+        [b'@', rest @ ..] if rest.starts_with(b"@") => Cow::Borrowed(OsStr::from_bytes(rest)),
+        _ => dot_prefix::link_name(original, options),
+    }
+}
+
+/// Returns whether `name` is the metadata directory of a version control system, these are never
+/// linked unless `--include-vcs` is given.
+fn is_vcs_dir(name: &OsStr) -> bool {
+    [".git", ".hg", ".svn"].iter().any(|vcs| name == *vcs)
+}
+
+/// Returns whether `link` resolves to `original`.
+fn is_linked(original: &Path, link: &Path) -> bool {
+    // Most links don't exist yet on a first run, which spares stating `original`.
+    let Ok(a) = link.metadata() else {
+        return false;
+    };
+
+    original
+        .metadata()
+        .is_ok_and(|b| a.ino() == b.ino() && a.dev() == b.dev())
+}
+
+/// The state of the link (or copy) of a package file.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    /// `link` resolves to `original`.
+    Linked,
+    /// `link` is a copy of `original`, which is copied rather than linked.
+    Copied,
+    /// Nothing exists at `link`.
+    Missing,
+    /// `link` is a symlink which doesn't resolve to `original`.
+    WrongTarget,
+    /// A file or directory exists at `link`.
+    Conflict,
+    /// `link` is a copy of `original` whose content differs.
+    CopyModified,
+}
+
+impl LinkState {
+    fn of(original: &Path, link: &Path, options: &Options) -> Self {
+        if is_copy_target(original, options) {
+            return match link.symlink_metadata() {
+                Err(_) => Self::Missing,
+                Ok(metadata) if !metadata.is_file() => Self::Conflict,
+                Ok(_) if freeze::same_content(original, link).unwrap_or(false) => Self::Copied,
+                Ok(_) => Self::CopyModified,
+            };
+        }
+
+        if is_linked(original, link) {
+            Self::Linked
+        } else {
+            match link.symlink_metadata() {
+                Err(_) => Self::Missing,
+                Ok(metadata) if metadata.is_symlink() => Self::WrongTarget,
+                Ok(_) => Self::Conflict,
+            }
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Linked => "linked",
+            Self::Copied => "copied",
+            Self::Missing => "missing",
+            Self::WrongTarget => "wrong target",
+            Self::Conflict => "conflict",
+            Self::CopyModified => "copy modified",
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let mut args = Args::from_env(true)?;
+    let mut context = Context::from_env(&mut args.options)?;
+
+    // Packages take precedence over plugins of the same name.
+    if let Command::External { name, .. } = &args.command {
+        if context.package_path(name).is_dir() {
+            args = Args::from_env(false)?;
+            context = Context::from_env(&mut args.options)?;
+        }
+    }
+
+    let Args {
+        command,
+        mut package_spec,
+        mut options,
+    } = args;
+
+    if let Some(mode) = options.umask {
+        umask::set(mode);
+    }
+
+    options.parents_record = parents::record(&context);
+
+    if options.verbosity > 1 {
+        println!(
+            "Using packages root {} ({})",
+            context.packages_root.display(),
+            context.packages_root_origin
+        );
+    }
+
+    // Hosts of `hosts.toml` may have their own packages.
+    if matches!(
+        command,
+        Command::Report | Command::Targets | Command::Status | Command::Check | Command::Prune
+    ) && matches!(package_spec, PackageSpec::None)
+        && options.host.is_none()
+    {
+        package_spec = PackageSpec::All;
+    }
+
+    // Deployments may take their packages from `hosts.toml`.
+    if command.takes_packages()
+        && !matches!(command, Command::Deploy(_))
+        && options.host.is_none()
+        && matches!(package_spec, PackageSpec::None)
+    {
+        bail!("No packages specified");
+    }
+
+    if options.interactive && !options.unlink && options.output_sh {
+        bail!("`--interactive` can't be used with `--output sh`");
+    }
+
+    if options.interactive && options.unlink {
+        let packages = context.packages(package_spec)?;
+
+        options.selection = Some(interactive::select(&context, &packages, &options)?);
+        package_spec = PackageSpec::List(packages.into_vec());
+    }
+
+    options.packages_root = Some(context.packages_root.to_path_buf());
+
+    // Every file replaced by a run is backed up into the same directory. Copies replaced with
+    // `--force` always are.
+    if matches!(command, Command::Link | Command::Restow)
+        && (options.backup.is_some() || options.interactive || options.force)
+    {
+        options.backup_session = Some(backup::session(
+            &context,
+            options.backup.as_deref().unwrap_or(Path::new("")),
+        )?);
+    }
+
+    if options.dry_run && !options.quick && !options.output_sh {
+        println!("Dry run mode, no changes will be made.");
+    }
+
+    match command {
+        Command::Link => link(&context, package_spec, &options),
+        Command::Scan => scan::run(&context, &options),
+        Command::Diff => diff::run(&context, package_spec, &options),
+        Command::GitHook(None) => bail!("Missing action, expected `install` or `uninstall`"),
+        Command::GitHook(Some(action)) => githook::run(&context, action, &options),
+        Command::Graph => graph::run(&context, package_spec, &options),
+        Command::Report => report::run(&context, package_spec, &options),
+        Command::Freeze => freeze::freeze(&context, package_spec, &options),
+        Command::Thaw => freeze::thaw(&context, package_spec, &options),
+        Command::Deploy(host) => deploy::run(&context, host.as_deref(), package_spec, &options),
+        Command::Export(None) => {
+            bail!("Missing format, expected `script`, `ansible`, `tmpfiles` or `json-schema`")
+        }
+        Command::Export(Some(format)) => export::run(&context, format, package_spec, &options),
+        Command::Targets => targets::run(&context, package_spec, &options),
+        Command::Status => match &options.host {
+            Some(host) => deploy::status(&context, host, package_spec, &options),
+            None => status::run(&context, package_spec, &options),
+        },
+        Command::Check => check::run(&context, package_spec, &options),
+        Command::Prune => prune::run(&context, package_spec, &options),
+        Command::Restow => prune::restow(&context, package_spec, &options),
+        Command::Orphans => orphans::run(&context, package_spec, &options),
+        Command::Backups if options.prune => backup::prune(&context, &options),
+        Command::Backups => backup::list(&context, &options),
+        Command::Restore(None) => bail!("Missing backup ID, see `xdot backups`"),
+        Command::Restore(Some(id)) => backup::restore(&context, &id, &options),
+        Command::Retry => retry::run(&context, &mut options),
+        Command::Doctor => doctor::run(&context, &options),
+        Command::VerifyRepo => verify::run(&context, &options),
+        Command::List => list::list(&context, &options),
+        Command::Search(None) => bail!("Missing search term"),
+        Command::Search(Some(term)) => list::search(&context, &term),
+        Command::Blame(None) => bail!("Missing target"),
+        Command::Blame(Some(target)) => blame::run(&context, &target, &options),
+        Command::Explain(None) => bail!("Missing path"),
+        Command::Explain(Some(path)) => explain::run(&context, &path, &options),
+        Command::State(None) => bail!("Missing action, expected `export` or `diff`"),
+        Command::State(Some(action)) => state::run(&context, action, &options),
+        Command::New(None) => bail!("Missing package name"),
+        Command::New(Some(package)) => new::run(&context, &package, &options),
+        Command::Adopt { package: None, .. } => bail!("Missing package name"),
+        Command::Adopt {
+            package: Some(package),
+            paths,
+        } => adopt::run(&context, &package, &paths, &options),
+        Command::Completions(shell) => completions::run(&context, shell, &options),
+        Command::Bench => bench::run(&options),
+        Command::External { program, args, .. } => plugin::exec(&context, &program, &args),
+    }
+}
+
+/// Symlinks (or unlinks) the packages matching `package_spec`.
+fn link(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let packages = context.packages(package_spec)?;
+
+    context.check_vars(&packages, options)?;
+
+    // Whole runs are skipped when there is nothing to do, which is cheaper than planning them.
+    let is_plain = options.verbosity == 0
+        && !options.output_sh
+        && !options.keep_going
+        && options.conflicts_report.is_none();
+
+    if is_plain && is_up_to_date(context, &packages, options)? {
+        if !options.quick {
+            println!("Up to date.");
+        }
+
+        return Ok(());
+    }
+
+    preflight(context, &packages, options)?;
+
+    if let Some(command) = &options.snapshot {
+        if options.output_sh {
+            println!("{}", command.to_string_lossy());
+        } else if options.dry_run {
+            println!("Skipping snapshot: {}", command.to_string_lossy());
+        } else {
+            snapshot(command)?;
+        }
+    }
+
+    let notification = match &options.notify {
+        Some(command) if !options.dry_run => {
+            Some((command, notify::states(context, &packages, options)?))
+        }
+        _ => None,
+    };
+
+    let mut conflicts = Vec::new();
+    let mut failures = Vec::new();
+    let mut skipped = Vec::new();
+    let mut overlay = Overlay::default();
+    // The targets of the package entries, only directories created for them are removed once
+    // unlinked.
+    let mut links = Vec::new();
+
+    for package in packages.iter() {
+        let package_path = context.package_path(package);
+
+        if !options.quick {
+            println!(
+                "{}{} config for `{}` ({})",
+                if options.output_sh { "# " } else { "" },
+                if options.unlink {
+                    "Unlinking"
+                } else {
+                    "Linking"
+                },
+                package.to_string_lossy(),
+                package_path.display()
+            );
+        }
+
+        let mut package_conflicts = Vec::new();
+
+        for (original, link) in context.package_entries(&package_path, options)? {
+            links.push(link.clone());
+
+            if let Err(error) = apply_entry(
+                context,
+                &original,
+                &link,
+                options,
+                &mut package_conflicts,
+                &mut skipped,
+                &mut overlay,
+            ) {
+                // Blips which outlasted retries are reported once the run is over.
+                if !options.keep_going && !transient::is_transient(&error) {
+                    return Err(error);
+                }
+
+                retry::report(&error);
+
+                failures.push(retry::Failure {
+                    package: package.to_string_lossy().into_owned(),
+                    original,
+                    link,
+                });
+            }
+        }
+
+        conflicts.extend(package_conflicts.into_iter().map(|(original, link)| {
+            conflicts::Conflict {
+                package: package.to_string_lossy().into_owned(),
+                original,
+                link,
+            }
+        }));
+    }
+
+    if options.unlink {
+        parents::remove_empty(&links, options)?;
+    }
+
+    report_skipped(&skipped);
+
+    if let Some((command, before)) = &notification {
+        notify::send(context, &packages, before, command, options);
+    }
+
+    if let Some(path) = &options.conflicts_report {
+        conflicts::write(path, &conflicts)?;
+
+        if !conflicts.is_empty() {
+            bail!("{} conflict(s), see {}", conflicts.len(), path.display());
+        }
+    }
+
+    if (options.keep_going || !failures.is_empty()) && !options.dry_run {
+        retry::save(context, &failures, options)?;
+    }
+
+    if !failures.is_empty() {
+        bail!(
+            "{} operation(s) failed, run `xdot retry{}` once fixed",
+            failures.len(),
+            if options.unlink { " --unlink" } else { "" }
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks what would make linking (or unlinking) `packages` fail halfway, before anything is done.
+fn preflight(context: &Context, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+    if !options.unlink {
+        context.check_limits(packages, options)?;
+        context.check_targets(packages, options)?;
+        context.check_collisions(packages, options)?;
+    }
+
+    ownership::check(context, packages, options)
+}
+
+/// Returns whether every file of `packages` is linked (or, with `--unlink`, none is).
+fn is_up_to_date(context: &Context, packages: &[Box<OsStr>], options: &Options) -> Result<bool> {
+    for package in packages {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            if !options.unlink && !modes::drifted(context, &original, options).is_empty() {
+                return Ok(false);
+            }
+
+            let mut up_to_date = true;
+
+            let walked = walk_files(context, &original, &link, options, &mut |original, link| {
+                let state = LinkState::of(original, link, options);
+
+                up_to_date &=
+                    matches!(state, LinkState::Linked | LinkState::Copied) != options.unlink;
+
+                // Directories are unfolded with `--no-folding`.
+                up_to_date &= options.unlink
+                    || state == LinkState::Copied
+                    || !options.no_folding
+                    || options.strategy == LinkStrategy::Hardlink
+                    || link
+                        .symlink_metadata()
+                        .is_ok_and(|metadata| metadata.is_symlink());
+
+                // Linking makes them executable.
+                up_to_date &= options.unlink
+                    || !executables::is_meant_executable(original)
+                    || executables::is_executable(original);
+
+                Ok(())
+            });
+
+            // Unreadable files are reported by a full run.
+            if walked.is_err() || !up_to_date {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Links (or unlinks) a top-level package entry.
+fn apply_entry(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+    skipped: &mut Vec<(PathBuf, String)>,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    if !options.unlink {
+        modes::apply(context, original, link, options)?;
+    }
+
+    if wsl::is_windows_target(original) {
+        wsl::copy_or_descend(context, original, link, options, conflicts, overlay)
+    } else {
+        symlink_or_descend(
+            context, original, link, options, conflicts, skipped, overlay,
+        )
+    }
+}
+
+/// Runs the `--snapshot` command, aborting the run if it fails.
+fn snapshot(command: &OsStr) -> Result<()> {
+    println!("Taking snapshot: {}", command.to_string_lossy());
+
+    let output = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .context("Unable to execute snapshot command")?;
+
+    if !output.status.success() {
+        bail!("Snapshot command failed: {}", output.status);
+    }
+
+    // Snapshot tools usually print the identifier of what they created.
+    if let Some(id) = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+    {
+        println!("Snapshot: {id}");
+    }
+
+    Ok(())
+}
+
+/// Returns a substring with the `U+0040 AT SIGN (@)` prefix removed.
+///
+/// If the string starts with a `U+0040 AT SIGN (@)`, returns substring after the prefix, wrapped
+/// in `Some`. The prefix is removed exactly once.
+///
+/// If the string does not start with a `U+0040 AT SIGN (@)`, or starts with two of them (a
+/// literal `@`, see [`entry_link_name`]), returns `None`.
+fn strip_at_sign_prefix(file_name: &OsStr) -> Option<&OsStr> {
+    let file_name = file_name.as_bytes();
+
+    if file_name[0] == b'@' && file_name.get(1) != Some(&b'@') {
+        Some(OsStr::from_bytes(&file_name[1..]))
+    } else {
+        None
+    }
+}
+
+/// Symlink the children of `original` to the children of `link`.
+fn descend_and_symlink(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+    skipped: &mut Vec<(PathBuf, String)>,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    let entries = match original.read_dir() {
+        Ok(entries) => entries,
+        Err(error) => return skip_unreadable(original, error.to_string(), options, skipped),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if is_excluded(context, &path, options) {
+            continue;
+        }
+
+        symlink_or_descend(
+            context,
+            &path,
+            &link.join(dot_prefix::link_name(&path, options)),
+            options,
+            conflicts,
+            skipped,
+            overlay,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Symlink `original` to `link`, or, if `original` already exists and is a directory, calls [`descend_and_symlink`].
+///
+/// Conflicts are collected into `conflicts` with `--conflicts-report`, otherwise they are errors.
+/// With `--dry-run`, changes are recorded into `overlay` instead of being made.
+fn symlink_or_descend(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+    skipped: &mut Vec<(PathBuf, String)>,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    let mut conflict =
+        |message: String| report_conflict(original, link, message, options, conflicts);
+
+    let file_type = match transient::retry(options, || original.symlink_metadata()) {
+        Ok(metadata) => metadata.file_type(),
+        Err(error) => return skip_unreadable(original, error.to_string(), options, skipped),
+    };
+
+    if file_type.is_fifo()
+        || file_type.is_socket()
+        || file_type.is_block_device()
+        || file_type.is_char_device()
+    {
+        skip(original, SkipReason::SpecialFile, options);
+
+        return Ok(());
+    }
+
+    if !options.unlink && !matches!(options.dangling_sources, DanglingPolicy::Link) {
+        if let Some(reason) = dangling_reason(original, file_type) {
+            return skip_unreadable(original, reason, options, skipped);
+        }
+    }
+
+    if !options.include_vcs && is_vcs_dir(original.file_name().unwrap_or_default()) {
+        skip(original, SkipReason::VcsMetadata, options);
+
+        return Ok(());
+    }
+
+    // e.g. a directory whose files are all linked elsewhere by `links.toml`, whatever is at its
+    // target.
+    if file_type.is_dir()
+        && !is_foldable(context, original, options)
+        && !holds_files(context, original, options)
+    {
+        return Ok(());
+    }
+
+    if is_copied(original, file_type.is_dir(), options) {
+        return wsl::copy_or_descend(context, original, link, options, conflicts, overlay);
+    }
+
+    if !options.unlink && file_type.is_file() && matches_selection("link", original, link, options)
+    {
+        executables::ensure(original, options)?;
+    }
+
+    let planned = overlay.get(link);
+    let linked = planned.is_linked(original);
+
+    // Symlinks which don't resolve to `original` are handled first.
+    if let Some(target) = planned.read_link() {
+        if !planned.exists() {
+            // Linked with `--dangling-sources link`.
+            if options.unlink
+                && (target == original
+                    || relative_path(original, link).is_ok_and(|path| path == target))
+            {
+                if is_selected("unlink", original, link, options) {
+                    print_removal(link, options);
+
+                    if options.dry_run {
+                        overlay.remove(link);
+                    } else {
+                        std::fs::remove_file(link).context("Unable to remove symlink")?;
+                    }
+                }
+
+                return Ok(());
+            }
+
+            if options.unlink {
+                skip(link, SkipReason::BrokenLink, options);
+
+                return Ok(());
+            }
+
+            if options.keep_broken {
+                return conflict(format!(
+                    "{} is a broken symlink to {}",
+                    link.display(),
+                    target.display()
+                ));
+            }
+
+            if !is_selected("link", original, link, options) {
+                return Ok(());
+            }
+
+            if options.output_sh {
+                print_removal(link, options);
+                print_link(original, link, options);
+            } else {
+                println!(
+                    "{} (replacing broken symlink to {})",
+                    term::paint(
+                        &term::link_line(link, original, options.verbosity > 0),
+                        term::Color::Green
+                    ),
+                    target.display()
+                );
+            }
+
+            if options.dry_run {
+                plan_link(original, link, options, overlay);
+            } else {
+                std::fs::remove_file(link)
+                    .with_context(|| format!("Unable to remove {}", link.display()))?;
+                create_link(original, link, options)?;
+            }
+
+            return Ok(());
+        }
+
+        if !options.unlink && !linked && original.is_dir() {
+            if let Some(folded) = folded_directory(link, &target, options) {
+                return unfold(
+                    context,
+                    link,
+                    &folded,
+                    Some(original),
+                    options,
+                    conflicts,
+                    skipped,
+                    overlay,
+                );
+            }
+        }
+
+        if !linked && options.follow_dir_symlinks && planned.is_dir() {
+            if !original.is_dir() {
+                return conflict(format!("{} already exists", link.display()));
+            }
+
+            if options.verbosity > 0 {
+                println!(
+                    "{}Descending into symlinked directory: {} (resolved to {})",
+                    if options.output_sh { "# " } else { "" },
+                    link.display(),
+                    link.canonicalize()?.display()
+                );
+            }
+
+            return descend_and_symlink(
+                context, original, link, options, conflicts, skipped, overlay,
+            );
+        }
+
+        if !linked {
+            if options.unlink {
+                skip(link, SkipReason::ForeignLink, options);
+
+                return Ok(());
+            }
+
+            if options.interactive {
+                if resolve_conflict(original, link, None, options)? {
+                    place_link(original, link, options, overlay)?;
+                }
+
+                return Ok(());
+            }
+
+            return conflict(format!(
+                "{} is a symlink to {}, not to {}",
+                link.display(),
+                target.display(),
+                original.display()
+            ));
+        }
+    }
+
+    if linked {
+        if options.unlink {
+            if !is_selected("unlink", original, link, options) {
+                return Ok(());
+            }
+
+            print_removal(link, options);
+
+            if options.dry_run {
+                overlay.remove(link);
+            } else {
+                std::fs::remove_file(link).context("Unable to remove symlink")?;
+            }
+        } else if (options.no_folding || !is_foldable(context, original, options))
+            && original.is_dir()
+            && planned.read_link().is_some()
+        {
+            unfold(
+                context, link, original, None, options, conflicts, skipped, overlay,
+            )?;
+        } else {
+            skip(link, SkipReason::AlreadyLinked, options);
+        }
+    } else if planned.exists() {
+        if options.force && planned.file().is_some() {
+            if !is_selected("link", original, link, options) {
+                return Ok(());
+            }
+
+            if options.output_sh {
+                backup::remove(link, options)?;
+                print_link(original, link, options);
+            } else {
+                println!(
+                    "{} (replacing existing file)",
+                    term::paint(
+                        &term::link_line(link, original, options.verbosity > 0),
+                        term::Color::Green
+                    )
+                );
+
+                backup::remove(link, options)?;
+            }
+
+            if options.dry_run {
+                plan_link(original, link, options, overlay);
+            } else {
+                create_link(original, link, options)?;
+            }
+
+            return Ok(());
+        }
+
+        if !planned.is_dir() || !original.is_dir() {
+            if let Some(existing) = planned.file().filter(|_| options.interactive) {
+                if original.is_file() {
+                    if resolve_conflict(original, link, Some(existing), options)? {
+                        place_link(original, link, options, overlay)?;
+                    }
+
+                    return Ok(());
+                }
+            }
+
+            // Helps deciding between adopting the existing file, `--force` or leaving it.
+            let identical = match planned.file() {
+                Some(existing) if !options.output_sh && original.is_file() => {
+                    diff::show_conflict(original, existing)
+                }
+                _ => false,
+            };
+
+            return conflict(format!(
+                "{} already exists{}",
+                link.display(),
+                if identical {
+                    " (with the same content)"
+                } else {
+                    ""
+                }
+            ));
+        }
+
+        if options.verbosity > 0 {
+            println!(
+                "{}Descending into preexisting directory: {}",
+                if options.output_sh { "# " } else { "" },
+                link.display()
+            );
+        }
+
+        if !options.unlink && selects_any(context, original, link, options) {
+            modes::ensure(context, original, link, options)?;
+        }
+
+        descend_and_symlink(
+            context, original, link, options, conflicts, skipped, overlay,
+        )?;
+    } else if options.unlink {
+        skip(link, SkipReason::Missing, options);
+    } else if (options.strategy == LinkStrategy::Hardlink
+        || options.no_folding
+        || !is_foldable(context, original, options))
+        && original.is_dir()
+    {
+        // Directories can't be hard linked, and aren't linked with `--no-folding` or when some of
+        // their files are copied, renamed or linked elsewhere.
+        if !selects_any(context, original, link, options) {
+            skip(link, SkipReason::Filtered, options);
+
+            return Ok(());
+        }
+
+        parents::create(link, options, overlay)?;
+
+        if options.output_sh {
+            println!("mkdir {}", shell_quote(link.as_os_str()));
+        } else {
+            println!("Creating directory: {}", link.display());
+        }
+
+        if options.dry_run {
+            overlay.create_dir(link);
+        } else {
+            transient::create_dir(link, options)
+                .with_context(|| format!("Unable to create {}", link.display()))?;
+
+            ownership::preserve(link, options)?;
+            modes::ensure(context, original, link, options)?;
+        }
+
+        descend_and_symlink(
+            context, original, link, options, conflicts, skipped, overlay,
+        )?;
+    } else {
+        if !is_selected("link", original, link, options) {
+            return Ok(());
+        }
+
+        parents::create(link, options, overlay)?;
+
+        print_link(original, link, options);
+
+        if options.dry_run {
+            plan_link(original, link, options, overlay);
+        } else {
+            create_link(original, link, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether the package file `original` is copied rather than linked, per `xdot.toml`.
+fn is_copied(original: &Path, is_dir: bool, options: &Options) -> bool {
+    options.copied.as_ref().is_some_and(|copied| {
+        original.starts_with(copied.path())
+            && copied
+                .matched_path_or_any_parents(original, is_dir)
+                .is_ignore()
+    })
+}
+
+/// Returns whether the package file `original`, found when walking a package, is copied rather
+/// than linked: per `xdot.toml`, or because it is linked into `@WINHOME`.
+fn is_copy_target(original: &Path, options: &Options) -> bool {
+    is_copied(original, false, options) || wsl::is_windows_file(original)
+}
+
+/// Returns whether the package directory `original` can be linked as a whole: not if some of its
+/// files are copied, linked under another name or elsewhere, or not linked at all.
+fn is_foldable(context: &Context, original: &Path, options: &Options) -> bool {
+    if options.copied.is_none()
+        && !dot_prefix::is_enabled(original, options)
+        && !links::maps_below(context, original)
+        && !xdotignore::ignores_below(original, options)
+        && options.subset.is_none()
+    {
+        return true;
+    }
+
+    // Unreadable directories are reported when linking.
+    let Ok(entries) = original.read_dir() else {
+        return true;
+    };
+
+    entries.flatten().all(|entry| {
+        let path = entry.path();
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+
+        !is_excluded(context, &path, options)
+            && !is_copied(&path, is_dir, options)
+            && *dot_prefix::link_name(&path, options) == *entry.file_name()
+            && (!is_dir || is_foldable(context, &path, options))
+    })
+}
+
+/// Returns whether the package directory `original` holds files to link, rather than only files
+/// left out or linked elsewhere.
+fn holds_files(context: &Context, original: &Path, options: &Options) -> bool {
+    let mut found = false;
+
+    // Unreadable files are reported when linking.
+    let _ = walk_files(context, original, original, options, &mut |_, _| {
+        found = true;
+
+        Ok(())
+    });
+
+    found
+}
+
+/// Returns the directory of another package which the symlink `link` (to `target`) folds, if it
+/// does.
+fn folded_directory(link: &Path, target: &Path, options: &Options) -> Option<PathBuf> {
+    let folded = link.parent().unwrap_or(Path::new("/")).join(target);
+
+    let root = options.packages_root.as_deref()?.canonicalize().ok()?;
+
+    (folded.is_dir() && folded.canonicalize().ok()?.starts_with(root)).then_some(folded)
+}
+
+/// Replaces the symlink `link` to the directory `folded` (of another package, or of the same one
+/// with `--no-folding`) with a directory holding links to its entries, then to those of
+/// `original` (the package directory which required the unfolding) if any. If linking either
+/// fails, the symlink is restored.
+#[allow(clippy::too_many_arguments)]
+fn unfold(
+    context: &Context,
+    link: &Path,
+    folded: &Path,
+    original: Option<&Path>,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+    skipped: &mut Vec<(PathBuf, String)>,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    if options.output_sh {
+        print_removal(link, options);
+        println!("mkdir {}", shell_quote(link.as_os_str()));
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &format!(
+                    "Unfolding {} (linked to {})",
+                    link.display(),
+                    folded.display()
+                ),
+                term::Color::Yellow
+            )
+        );
+    }
+
+    let descend = |conflicts: &mut Vec<_>, skipped: &mut Vec<_>, overlay: &mut Overlay| {
+        descend_and_symlink(context, folded, link, options, conflicts, skipped, overlay)?;
+
+        match original {
+            Some(original) => descend_and_symlink(
+                context, original, link, options, conflicts, skipped, overlay,
+            ),
+            None => Ok(()),
+        }
+    };
+
+    if options.dry_run {
+        overlay.create_dir(link);
+
+        return descend(conflicts, skipped, overlay);
+    }
+
+    let target = link
+        .read_link()
+        .with_context(|| format!("Unable to read {}", link.display()))?;
+
+    std::fs::remove_file(link).with_context(|| format!("Unable to remove {}", link.display()))?;
+
+    let result = transient::create_dir(link, options)
+        .with_context(|| format!("Unable to create {}", link.display()))
+        .and_then(|()| ownership::preserve(link, options))
+        .and_then(|()| descend(conflicts, skipped, overlay));
+
+    // Everything in the directory was created above, folding it back leaves it as it was.
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(link);
+
+        if let Err(error) = std::os::unix::fs::symlink(&target, link) {
+            eprintln!(
+                "Warning: unable to fold {} back to {}: {error}",
+                link.display(),
+                target.display()
+            );
+        } else {
+            println!("Folded {} back to {}", link.display(), target.display());
+        }
+    }
+
+    result
+}
+
+/// Reports the conflict between `original` and what is at `link`: collected into `conflicts` with
+/// `--conflicts-report`, otherwise an error.
+fn report_conflict(
+    original: &Path,
+    link: &Path,
+    message: String,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    if options.conflicts_report.is_none() {
+        bail!(message);
+    }
+
+    println!(
+        "{}",
+        term::paint(&format!("Conflict: {message}"), term::Color::Red)
+    );
+
+    conflicts.push((original.to_owned(), link.to_owned()));
+
+    Ok(())
+}
+
+/// Resolves the conflict between `original` and the file `existing` (or foreign symlink) at `link`
+/// as answered with `--interactive`, returns whether `link` is free to be linked (or copied) to
+/// `original`.
+fn resolve_conflict(
+    original: &Path,
+    link: &Path,
+    existing: Option<&Path>,
+    options: &Options,
+) -> Result<bool> {
+    if !is_selected("link", original, link, options) {
+        return Ok(false);
+    }
+
+    match interactive::resolve(original, link, existing, options)? {
+        Resolution::Skip => {
+            skip(link, SkipReason::Filtered, options);
+
+            return Ok(false);
+        }
+        Resolution::Overwrite => backup::remove(link, options)?,
+        Resolution::Backup => backup::back_up(link, options)?,
+        Resolution::Adopt => {
+            println!("Moving {} to {}", link.display(), original.display());
+
+            if !options.dry_run {
+                backup::move_file(link, original)?;
+                executables::ensure(original, options)?;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Links `link` to `original`, where nothing exists (or the conflicting file was removed).
+fn place_link(
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    print_link(original, link, options);
+
+    if options.dry_run {
+        plan_link(original, link, options, overlay);
+    } else {
+        create_link(original, link, options)?;
+    }
+
+    Ok(())
+}
+
+/// Links `link` to `original` according to the link strategy.
+fn create_link(original: &Path, link: &Path, options: &Options) -> Result<()> {
+    if options.strategy == LinkStrategy::Hardlink {
+        let create = || std::fs::hard_link(original, link);
+        let is_created = |link: &Path| is_linked(original, link);
+
+        return transient::retry_creation(link, options, create, is_created).map_err(|error| {
+            if error.kind() == std::io::ErrorKind::CrossesDevices {
+                anyhow!(
+                    "Unable to hard link {} => {}: they are on different filesystems, \
+                    drop `--hardlink` to symlink it instead",
+                    link.display(),
+                    original.display()
+                )
+            } else {
+                anyhow!(error).context(format!(
+                    "Unable to hard link {} => {}",
+                    link.display(),
+                    original.display()
+                ))
+            }
+        });
+    }
+
+    let target = symlink_target(original, link, options)?;
+    let create = || symlink(&target, link);
+    let is_created = |link: &Path| link.read_link().is_ok_and(|existing| existing == target);
+
+    transient::retry_creation(link, options, create, is_created).with_context(|| {
+        format!(
+            "Unable to symlink {} => {}",
+            link.display(),
+            original.display()
+        )
+    })?;
+
+    ownership::preserve(link, options)
+}
+
+/// Records the link [`create_link`] would have made into `overlay`.
+fn plan_link(original: &Path, link: &Path, options: &Options, overlay: &mut Overlay) {
+    if options.strategy == LinkStrategy::Hardlink {
+        overlay.hard_link(original, link);
+    } else {
+        overlay.symlink(original, link);
+    }
+}
+
+/// Returns the content of a symlink at `link` to `original`: `original` itself, or, with
+/// `--relative`, [`relative_path`].
+fn symlink_target(original: &Path, link: &Path, options: &Options) -> Result<PathBuf> {
+    if options.strategy == LinkStrategy::RelativeSymlink {
+        relative_path(original, link)
+    } else {
+        Ok(original.to_owned())
+    }
+}
+
+/// Returns the path to `original` from the directory of `link`.
+fn relative_path(original: &Path, link: &Path) -> Result<PathBuf> {
+    // Symlinks among ancestors, e.g. `/home` → `/var/home`, are resolved so that the path goes
+    // through as few directories as possible.
+    let real = |path: &Path| -> Result<PathBuf> {
+        Ok(match path.parent().map(Path::canonicalize) {
+            Some(Ok(parent)) => parent.join(path.file_name().unwrap_or_default()),
+            _ => std::path::absolute(path)?,
+        })
+    };
+
+    let original = real(original)?;
+    let directory = real(link)?.parent().unwrap_or(Path::new("/")).to_owned();
+
+    let mut original_components = original.components().peekable();
+    let mut directory_components = directory.components().peekable();
+
+    while original_components.peek().is_some()
+        && original_components.peek() == directory_components.peek()
+    {
+        original_components.next();
+        directory_components.next();
+    }
+
+    Ok(directory_components
+        .map(|_| Component::ParentDir)
+        .chain(original_components)
+        .collect())
+}
+
+/// Prints the creation of a link, as a shell command with `--output sh`.
+fn print_link(original: &Path, link: &Path, options: &Options) {
+    if options.output_sh && options.strategy == LinkStrategy::Hardlink {
+        println!(
+            "ln {} {}",
+            shell_quote(original.as_os_str()),
+            shell_quote(link.as_os_str())
+        );
+    } else if options.output_sh {
+        println!(
+            "ln -s {} {}",
+            shell_quote(
+                symlink_target(original, link, options)
+                    .as_deref()
+                    .unwrap_or(original)
+                    .as_os_str()
+            ),
+            shell_quote(link.as_os_str())
+        );
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &term::link_line(link, original, options.verbosity > 0),
+                term::Color::Green
+            )
+        );
+    }
+}
+
+/// Prints the removal of a link, as a shell command with `--output sh`.
+fn print_removal(link: &Path, options: &Options) {
+    if options.output_sh {
+        println!("rm {}", shell_quote(link.as_os_str()));
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &format!(
+                    "Removing {}: {}",
+                    if link
+                        .symlink_metadata()
+                        .is_ok_and(|metadata| metadata.is_file())
+                    {
+                        "hard link"
+                    } else {
+                        "symlink"
+                    },
+                    link.display()
+                ),
+                term::Color::Yellow
+            )
+        );
+    }
+}
+
+/// Returns whether the operation matches `--filter` and the links picked with `--interactive`, if
+/// any, skipping it otherwise.
+fn is_selected(action: &str, original: &Path, link: &Path, options: &Options) -> bool {
+    let selected = matches_selection(action, original, link, options);
+
+    if !selected {
+        skip(link, SkipReason::Filtered, options);
+    }
+
+    selected
+}
+
+fn matches_selection(action: &str, original: &Path, link: &Path, options: &Options) -> bool {
+    options.filter.as_ref().is_none_or(|filter| {
+        filter.matches(&filter::Operation {
+            action,
+            source: original,
+            target: link,
+        })
+    }) && options
+        .selection
+        .as_ref()
+        .is_none_or(|selection| selection.contains(link))
+}
+
+/// Returns whether linking any file of the package file or directory `original`, at `link`, is
+/// selected (see [`is_selected`]). Directories are only created, and modes only fixed, for those.
+fn selects_any(context: &Context, original: &Path, link: &Path, options: &Options) -> bool {
+    if options.filter.is_none() && options.selection.is_none() {
+        return true;
+    }
+
+    let mut any = false;
+
+    let _ = walk_files(context, original, link, options, &mut |original, link| {
+        any |= matches_selection("link", original, link, options);
+
+        Ok(())
+    });
+
+    any
+}
+
+/// Why a file was left untouched.
+#[derive(Clone, Copy)]
+enum SkipReason {
+    AlreadyLinked,
+    NotLinked,
+    Missing,
+    Modified,
+    Filtered,
+    SpecialFile,
+    BrokenLink,
+    ForeignLink,
+    VcsMetadata,
+    UpToDate,
+    Unreadable,
+    CopyTarget,
+}
+
+impl SkipReason {
+    fn code(self) -> &'static str {
+        match self {
+            Self::AlreadyLinked => "already-linked",
+            Self::NotLinked => "not-linked",
+            Self::Missing => "missing",
+            Self::Modified => "modified",
+            Self::Filtered => "filtered",
+            Self::SpecialFile => "special-file",
+            Self::BrokenLink => "broken-link",
+            Self::ForeignLink => "foreign-link",
+            Self::VcsMetadata => "vcs-metadata",
+            Self::UpToDate => "up-to-date",
+            Self::Unreadable => "unreadable",
+            Self::CopyTarget => "copy-target",
+        }
+    }
+
+    fn explanation(self) -> &'static str {
+        match self {
+            Self::AlreadyLinked => "an identical symlink already exists",
+            Self::NotLinked => "it isn't a symlink to the package file",
+            Self::Missing => "nothing exists at this path",
+            Self::Modified => "its content differs from the package file",
+            Self::Filtered => "the operation doesn't match `--filter` or wasn't picked",
+            Self::SpecialFile => "sockets, FIFOs and devices can't be linked",
+            Self::BrokenLink => "it is a symlink to a path which doesn't exist",
+            Self::ForeignLink => "it is a symlink to another file",
+            Self::VcsMetadata => "version control metadata is only linked with `--include-vcs`",
+            Self::UpToDate => "an identical copy already exists",
+            Self::Unreadable => "the package file is broken or can't be read",
+            Self::CopyTarget => "it is copied rather than linked (`copy` in xdot.toml, `@WINHOME`)",
+        }
+    }
+}
+
+/// Reports a skipped file: its reason code with `-v`, along with an explanation with `-vv`.
+fn skip(path: &Path, reason: SkipReason, options: &Options) {
+    let comment = if options.output_sh { "# " } else { "" };
+
+    match options.verbosity {
+        0 => {}
+        1 => println!("{comment}Skipping {} ({})", path.display(), reason.code()),
+        _ => println!(
+            "{comment}Skipping {} ({}): {}",
+            path.display(),
+            reason.code(),
+            reason.explanation()
+        ),
+    }
+}
+
+/// Quotes `value` for use in a POSIX shell script, byte for byte: bytes which aren't UTF-8 are
+/// written with `printf`, POSIX shells having no escapes in quotes.
+fn shell_quote(value: &OsStr) -> String {
+    let mut quoted = String::new();
+
+    for chunk in value.as_bytes().utf8_chunks() {
+        if !chunk.valid().is_empty() {
+            quoted.push('\'');
+            quoted.push_str(&chunk.valid().replace('\'', r"'\''"));
+            quoted.push('\'');
+        }
+
+        for byte in chunk.invalid() {
+            let _ = write!(quoted, r#""$(printf '\{byte:03o}')""#);
+        }
+    }
+
+    if quoted.is_empty() {
+        quoted.push_str("''");
+    }
+
+    quoted
+}
+
+/// Asks a yes/no question on stdout and reads the answer from stdin, defaulting to no. With
+/// `--yes`, the answer is yes, and with `--no-input` the default, without reading stdin, so that
+/// provisioning scripts don't hang.
+fn prompt(question: &str, options: &Options) -> Result<bool> {
+    use std::io::Write;
+
+    if options.yes {
+        println!("{question} [y/N] y (--yes)");
+
+        return Ok(true);
+    }
+
+    if options.no_input {
+        println!("{question} [y/N] n (--no-input)");
+
+        return Ok(false);
+    }
+
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Unable to read answer")?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
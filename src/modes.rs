@@ -0,0 +1,210 @@
+//! `modes.toml`: the permissions of package files, which their links share, e.g. for
+//! `~/.ssh` and `~/.gnupg`:
+//!
+//! ```toml
+//! "@HOME/.ssh" = "700"
+//! "@HOME/.ssh/config" = "644"
+//! ```
+//!
+//! The mode of a directory applies to everything below it, down to the next path given, with the
+//! execute bits only kept by directories and executables (like `chmod -R u=rwX,go=`). Linking
+//! applies them, `xdot status` and `xdot doctor` report drift.
+
+use std::{
+    collections::BTreeMap,
+    fs::{Metadata, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{dot_prefix, is_excluded, read_toml, selects_any, shell_quote, term, Context, Options};
+
+pub const FILE: &str = "modes.toml";
+
+/// A package file (or directory) whose mode differs from `modes.toml`.
+pub struct Drift {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub expected: u32,
+}
+
+/// Reads the `modes.toml` of the package at `package_path`: package files, joined to it, along
+/// with their mode.
+pub fn read(package_path: &Path) -> Result<BTreeMap<PathBuf, u32>> {
+    let path = package_path.join(FILE);
+
+    let modes: BTreeMap<PathBuf, String> = read_toml(&path)?.unwrap_or_default();
+
+    modes
+        .into_iter()
+        .map(|(source, mode)| {
+            if source.as_os_str().is_empty()
+                || source.is_absolute()
+                || source.components().any(|c| c.as_os_str() == "..")
+            {
+                bail!(
+                    "Invalid {}: `{}` isn't a path inside the package",
+                    path.display(),
+                    source.display()
+                );
+            }
+
+            match u32::from_str_radix(&mode, 8) {
+                Ok(mode) if mode <= 0o777 => Ok((package_path.join(source), mode)),
+                _ => bail!(
+                    "Invalid {}: mode `{mode}` of `{}` isn't octal, e.g. `600`",
+                    path.display(),
+                    source.display()
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Returns whether modes are given for the package entry `entry` or for files below it.
+fn covers(context: &Context, entry: &Path) -> bool {
+    context.package_config_of(entry).is_some_and(|config| {
+        config
+            .modes
+            .keys()
+            .any(|path| path.starts_with(entry) || entry.starts_with(path))
+    })
+}
+
+/// Returns the mode the package file (or directory) `original`, with `metadata`, should have.
+fn expected(context: &Context, original: &Path, metadata: &Metadata) -> Option<u32> {
+    let config = context.package_config_of(original)?;
+
+    let mode = original
+        .ancestors()
+        .find_map(|ancestor| config.modes.get(ancestor))?;
+
+    if metadata.is_dir() || metadata.permissions().mode() & 0o111 != 0 {
+        Some(*mode)
+    } else {
+        Some(mode & !0o111)
+    }
+}
+
+/// Returns the package files and directories of the package entry `entry` whose mode differs from
+/// `modes.toml`.
+pub fn drifted(context: &Context, entry: &Path, options: &Options) -> Vec<Drift> {
+    let mut drifted = Vec::new();
+
+    if covers(context, entry) {
+        collect_drifted(context, entry, options, &mut drifted);
+    }
+
+    drifted
+}
+
+fn collect_drifted(
+    context: &Context,
+    original: &Path,
+    options: &Options,
+    drifted: &mut Vec<Drift>,
+) {
+    // Unreadable files are reported when linking, symlinks don't have a mode of their own.
+    let Ok(metadata) = original.symlink_metadata() else {
+        return;
+    };
+
+    if metadata.is_symlink() {
+        return;
+    }
+
+    if let Some(expected) = expected(context, original, &metadata) {
+        let mode = metadata.permissions().mode() & 0o7777;
+
+        if mode != expected {
+            drifted.push(Drift {
+                path: original.to_owned(),
+                mode,
+                expected,
+            });
+        }
+    }
+
+    if !metadata.is_dir() {
+        return;
+    }
+
+    let Ok(entries) = original.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        if !is_excluded(context, &entry.path(), options) {
+            collect_drifted(context, &entry.path(), options, drifted);
+        }
+    }
+}
+
+/// Gives the package files and directories of the package entry `entry`, linked at `link`, their
+/// mode. Only those holding files selected by `--filter` are, see [`selects_any`].
+pub fn apply(context: &Context, entry: &Path, link: &Path, options: &Options) -> Result<()> {
+    for drift in drifted(context, entry, options) {
+        if selects_any(
+            context,
+            &drift.path,
+            &link_below(entry, link, &drift.path, options),
+            options,
+        ) {
+            set(&drift.path, drift.expected, options)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the target of `path`, below the package entry `entry` linked at `link`.
+fn link_below(entry: &Path, link: &Path, path: &Path, options: &Options) -> PathBuf {
+    let mut original = entry.to_owned();
+    let mut target = link.to_owned();
+
+    for component in path.strip_prefix(entry).unwrap_or(Path::new("")) {
+        original.push(component);
+        target.push(dot_prefix::link_name(&original, options));
+    }
+
+    target
+}
+
+/// Gives the directory `link`, created (or descended into) for the package directory `original`,
+/// the mode of `original`.
+pub fn ensure(context: &Context, original: &Path, link: &Path, options: &Options) -> Result<()> {
+    // Not created with `--dry-run`.
+    let (Ok(metadata), Ok(link_metadata)) = (original.metadata(), link.symlink_metadata()) else {
+        return Ok(());
+    };
+
+    match expected(context, original, &metadata) {
+        Some(expected) if link_metadata.permissions().mode() & 0o7777 != expected => {
+            set(link, expected, options)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn set(path: &Path, mode: u32, options: &Options) -> Result<()> {
+    if options.output_sh {
+        println!("chmod {mode:03o} {}", shell_quote(path.as_os_str()));
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &format!("Setting mode {mode:03o}: {}", path.display()),
+                term::Color::Yellow
+            )
+        );
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    std::fs::set_permissions(path, Permissions::from_mode(mode))
+        .with_context(|| format!("Unable to set the mode of {}", path.display()))
+}
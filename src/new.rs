@@ -0,0 +1,129 @@
+//! `xdot new`: create a package, optionally from a template of the registry.
+//!
+//! The registry is a directory holding one template per subdirectory, `templates` in
+//! `config.toml` designates it (relative to `$HOME` unless absolute) or a git repository to clone,
+//! it defaults to `$XDG_DATA_HOME/xdot/templates`.
+
+use std::{ffi::OsStr, path::PathBuf, process::Command};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{freeze::copy_recursively, list, Context, Options};
+
+pub fn run(context: &Context, package: &OsStr, options: &Options) -> Result<()> {
+    let package_path = context.package_path(package);
+
+    if package_path.symlink_metadata().is_ok() {
+        bail!("{} already exists", package_path.display());
+    }
+
+    let Some(template) = &options.template else {
+        println!("Creating package {}", package_path.display());
+
+        if !options.dry_run {
+            std::fs::create_dir_all(&package_path)
+                .with_context(|| format!("Unable to create {}", package_path.display()))?;
+            std::fs::write(
+                package_path.join(list::README),
+                format!("# {}\n", package.to_string_lossy()),
+            )
+            .context("Unable to write README.md")?;
+        }
+
+        return Ok(());
+    };
+
+    let registry = registry(context, options)?;
+    let template_path = registry.join(template);
+
+    // With `--dry-run`, a git registry which isn't cloned yet can't be looked into.
+    let is_unfetched = options.dry_run && !registry.exists();
+
+    if !template_path.is_dir() && !is_unfetched {
+        bail!(
+            "Unknown template `{template}` ({})",
+            template_path.display()
+        );
+    }
+
+    println!(
+        "Creating package {} from {}",
+        package_path.display(),
+        template_path.display()
+    );
+
+    if !options.dry_run {
+        if let Some(parent) = package_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+
+        copy_recursively(&template_path, &package_path, options)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the registry directory, cloning (or updating) it first if it is a git repository. With
+/// `--dry-run`, that is only reported.
+fn registry(context: &Context, options: &Options) -> Result<PathBuf> {
+    let Some(templates) = &context.templates else {
+        return context
+            .resolve_var(OsStr::new("XDG_DATA_HOME"))
+            .map(|data| data.join("xdot/templates"))
+            .context("Unable to resolve `XDG_DATA_HOME`");
+    };
+
+    if !is_remote(templates) {
+        return Ok(context.home.join(templates));
+    }
+
+    let cache = context
+        .resolve_var(OsStr::new("XDG_CACHE_HOME"))
+        .context("Unable to resolve `XDG_CACHE_HOME`")?
+        .join("xdot/templates");
+
+    if options.dry_run {
+        println!(
+            "{} templates from {templates} into {}",
+            if cache.join(".git").is_dir() {
+                "Would update"
+            } else {
+                "Would fetch"
+            },
+            cache.display()
+        );
+
+        return Ok(cache);
+    }
+
+    let mut git = Command::new("git");
+
+    if cache.join(".git").is_dir() {
+        if options.verbosity > 0 {
+            println!("Updating templates from {templates}");
+        }
+
+        git.arg("-C")
+            .arg(&cache)
+            .args(["pull", "--quiet", "--ff-only"]);
+    } else {
+        println!("Fetching templates from {templates}");
+
+        git.args(["clone", "--quiet", "--depth", "1", templates])
+            .arg(&cache);
+    }
+
+    let status = git.status().context("Unable to execute git command")?;
+
+    if !status.success() {
+        bail!("Unable to fetch templates from {templates}: {status}");
+    }
+
+    Ok(cache)
+}
+
+/// Returns whether `templates` designates a git repository rather than a directory.
+fn is_remote(templates: &str) -> bool {
+    templates.contains("://") || templates.starts_with("git@")
+}
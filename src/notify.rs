@@ -0,0 +1,96 @@
+//! `--notify[=CMD]`: tell about the targets a run changed, so that runs from timers stay silent
+//! until they do something.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{walk_readable_files, Context, LinkState, Options};
+
+/// The state of the targets of `packages`, to be compared once the run is over.
+pub fn states(
+    context: &Context,
+    packages: &[Box<OsStr>],
+    options: &Options,
+) -> Result<Vec<(PathBuf, LinkState)>> {
+    let mut states = Vec::new();
+
+    for package in packages {
+        for (entry, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_readable_files(context, &entry, &link, options, &mut |original, link| {
+                states.push((link.to_owned(), LinkState::of(original, link, options)));
+
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(states)
+}
+
+/// Notifies about the targets whose state changed since `before`, if any.
+pub fn send(
+    context: &Context,
+    packages: &[Box<OsStr>],
+    before: &[(PathBuf, LinkState)],
+    command: &OsStr,
+    options: &Options,
+) {
+    let changed = match states(context, packages, options) {
+        Ok(after) => before
+            .iter()
+            .zip(after)
+            .filter(|((_, before), (_, after))| before != after)
+            .map(|((link, _), _)| link.clone())
+            .collect::<Vec<_>>(),
+        Err(error) => {
+            eprintln!("Warning: unable to notify: {error:#}");
+
+            return;
+        }
+    };
+
+    if changed.is_empty() {
+        return;
+    }
+
+    // The run itself succeeded, failing to notify doesn't change that.
+    if let Err(error) = run(command, &changed) {
+        eprintln!("Warning: unable to notify: {error:#}");
+    }
+}
+
+/// Runs `command` with `sh -c` and the changed targets as arguments, or `notify-send` when empty.
+fn run(command: &OsStr, changed: &[PathBuf]) -> Result<()> {
+    let status = if command.is_empty() {
+        let body = changed
+            .iter()
+            .map(|link| link.display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        std::process::Command::new("notify-send")
+            .args(["--app-name=xdot", "--"])
+            .arg(format!("xdot changed {} target(s)", changed.len()))
+            .arg(body)
+            .status()
+            .context("Unable to execute `notify-send`")?
+    } else {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .arg("sh")
+            .args(changed.iter().map(OsString::from))
+            .status()
+            .context("Unable to execute notification command")?
+    };
+
+    if !status.success() {
+        bail!("Notification command failed: {status}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+//! `xdot orphans`: list the files which no package provides in the directories packages link file
+//! by file, e.g. a stray `~/.config/nvim/scratch.lua`, to adopt or delete them. The inverse of
+//! conflicts.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{dot_prefix, is_excluded, is_vcs_dir, Context, Options, PackageSpec};
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    // The names packages provide in each directory, as several packages may link into one.
+    let mut directories = BTreeMap::new();
+
+    for package in context.packages(package_spec)?.iter() {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            collect_directories(context, &original, &link, options, &mut directories)?;
+        }
+    }
+
+    let mut orphans = 0;
+
+    for (directory, names) in &directories {
+        // Directories which don't exist yet have no orphans.
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            if names.contains(&entry.file_name())
+                || !entry.file_type().is_ok_and(|file_type| file_type.is_file())
+            {
+                continue;
+            }
+
+            println!("{}", entry.path().display());
+
+            orphans += 1;
+        }
+    }
+
+    if orphans == 0 {
+        println!("No orphans.");
+    }
+
+    Ok(())
+}
+
+/// Collects the names provided by the package directory `original` (and its subdirectories) when
+/// `link` is a directory rather than a symlink to it.
+fn collect_directories(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    directories: &mut BTreeMap<PathBuf, BTreeSet<OsString>>,
+) -> Result<()> {
+    if !original.symlink_metadata()?.is_dir()
+        || !link
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Ok(());
+    }
+
+    for entry in original
+        .read_dir()
+        .with_context(|| format!("Unable to descend into {}", original.display()))?
+    {
+        let entry = entry?;
+
+        if is_vcs_dir(&entry.file_name()) || is_excluded(context, &entry.path(), options) {
+            continue;
+        }
+
+        let name = dot_prefix::link_name(&entry.path(), options).into_owned();
+
+        collect_directories(
+            context,
+            &entry.path(),
+            &link.join(&name),
+            options,
+            directories,
+        )?;
+
+        directories.entry(link.to_owned()).or_default().insert(name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! The changes a `--dry-run` would have made, so that later decisions of the same run see them.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+enum Node {
+    Symlink(PathBuf),
+    /// A hard link to the contained file.
+    Hardlink(PathBuf),
+    Directory,
+    /// A copy of the contained file.
+    Copy(PathBuf),
+    Removed,
+}
+
+/// The state of a path once the recorded changes are applied.
+pub enum Planned {
+    /// Untouched, the filesystem can be queried at the contained path (which accounts for
+    /// recorded symlinks among its ancestors).
+    Unchanged(PathBuf),
+    Missing,
+    Symlink(PathBuf),
+    Hardlink(PathBuf),
+    Directory,
+    Copy(PathBuf),
+}
+
+impl Planned {
+    /// Returns the target of a symlink.
+    pub fn read_link(&self) -> Option<PathBuf> {
+        match self {
+            Self::Unchanged(path) => path.read_link().ok(),
+            Self::Symlink(target) => Some(target.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns whether something exists, including broken symlinks.
+    pub fn exists_no_follow(&self) -> bool {
+        match self {
+            Self::Unchanged(path) => path.symlink_metadata().is_ok(),
+            Self::Missing => false,
+            _ => true,
+        }
+    }
+
+    /// Returns whether something exists, following symlinks.
+    pub fn exists(&self) -> bool {
+        match self {
+            Self::Unchanged(path) | Self::Symlink(path) => path.exists(),
+            Self::Missing => false,
+            Self::Hardlink(_) | Self::Directory | Self::Copy(_) => true,
+        }
+    }
+
+    /// Returns whether a directory exists, following symlinks.
+    pub fn is_dir(&self) -> bool {
+        match self {
+            Self::Unchanged(path) | Self::Symlink(path) => path.is_dir(),
+            Self::Directory => true,
+            Self::Missing | Self::Hardlink(_) | Self::Copy(_) => false,
+        }
+    }
+
+    /// Returns whether this resolves to `original`.
+    pub fn is_linked(&self, original: &Path) -> bool {
+        match self {
+            Self::Unchanged(path) | Self::Symlink(path) | Self::Hardlink(path) => {
+                crate::is_linked(original, path)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the file holding the content of a regular file.
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            Self::Unchanged(path) => path
+                .symlink_metadata()
+                .is_ok_and(|metadata| metadata.is_file())
+                .then_some(path),
+            Self::Hardlink(original) | Self::Copy(original) => Some(original),
+            _ => None,
+        }
+    }
+}
+
+/// Changes recorded during a `--dry-run`, by link path.
+#[derive(Default)]
+pub struct Overlay(HashMap<PathBuf, Node>);
+
+impl Overlay {
+    pub fn symlink(&mut self, original: &Path, link: &Path) {
+        self.0
+            .insert(link.to_owned(), Node::Symlink(original.to_owned()));
+    }
+
+    pub fn hard_link(&mut self, original: &Path, link: &Path) {
+        self.0
+            .insert(link.to_owned(), Node::Hardlink(original.to_owned()));
+    }
+
+    pub fn create_dir(&mut self, path: &Path) {
+        self.0.insert(path.to_owned(), Node::Directory);
+    }
+
+    pub fn copy(&mut self, original: &Path, link: &Path) {
+        self.0
+            .insert(link.to_owned(), Node::Copy(original.to_owned()));
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.0.insert(path.to_owned(), Node::Removed);
+    }
+
+    /// Returns the state of `path` once the recorded changes are applied.
+    pub fn get(&self, path: &Path) -> Planned {
+        match self.0.get(path) {
+            Some(Node::Symlink(target)) => return Planned::Symlink(target.clone()),
+            Some(Node::Hardlink(original)) => return Planned::Hardlink(original.clone()),
+            Some(Node::Directory) => return Planned::Directory,
+            Some(Node::Copy(original)) => return Planned::Copy(original.clone()),
+            Some(Node::Removed) => return Planned::Missing,
+            None => {}
+        }
+
+        // The closest recorded ancestor decides.
+        for ancestor in path.ancestors().skip(1) {
+            match self.0.get(ancestor) {
+                Some(Node::Symlink(target)) => {
+                    let rest = path.strip_prefix(ancestor).unwrap_or(path);
+
+                    return Planned::Unchanged(target.join(rest));
+                }
+                Some(_) => return Planned::Missing,
+                None => {}
+            }
+        }
+
+        Planned::Unchanged(path.to_owned())
+    }
+}
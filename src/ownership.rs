@@ -0,0 +1,147 @@
+//! Target directories owned by another user: they are detected before making any change, and
+//! only operated on with `--allow-foreign-dirs`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{CStr, OsStr},
+    fmt::Write as _,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{is_linked, walk_readable_files, Context, Options};
+
+/// Fails with the target directories of `packages` which are owned by another user, unless
+/// `--allow-foreign-dirs` is given.
+pub fn check(context: &Context, packages: &[Box<OsStr>], options: &Options) -> Result<()> {
+    if options.allow_foreign_dirs {
+        return Ok(());
+    }
+
+    let uid = unsafe { libc::geteuid() };
+
+    let mut foreign = BTreeMap::<PathBuf, u32>::new();
+
+    // The closest existing ancestor and its owner, by parent directory of the links: files mostly
+    // share their directory.
+    let mut owners = HashMap::<PathBuf, Option<(PathBuf, u32)>>::new();
+
+    for package in packages {
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_readable_files(context, &original, &link, options, &mut |original, link| {
+                // Only directories which would be modified matter.
+                if is_linked(original, link) != options.unlink {
+                    return Ok(());
+                }
+
+                let Some(parent) = link.parent() else {
+                    return Ok(());
+                };
+
+                if !owners.contains_key(parent) {
+                    let owner = parent.ancestors().find_map(|ancestor| {
+                        Some((ancestor.to_owned(), ancestor.metadata().ok()?.uid()))
+                    });
+
+                    owners.insert(parent.to_owned(), owner);
+                }
+
+                if let Some((directory, owner)) = &owners[parent] {
+                    if *owner != uid {
+                        foreign.insert(directory.clone(), *owner);
+                    }
+                }
+
+                Ok(())
+            })?;
+        }
+    }
+
+    if foreign.is_empty() {
+        return Ok(());
+    }
+
+    let mut message = String::from("The following target directories are owned by another user:");
+
+    for (directory, owner) in &foreign {
+        write!(
+            message,
+            "\n  {} (owned by {})",
+            directory.display(),
+            user_name(*owner)
+        )?;
+    }
+
+    message.push_str("\nUse `--allow-foreign-dirs` to operate on them anyway.");
+
+    bail!(message)
+}
+
+/// Gives `link`, a link, copy or directory which was just created, the ownership of its directory
+/// when running as root, so that its owner can manage it. xdot keeps running as root: nothing is
+/// done as that owner, whose permissions are therefore not checked.
+pub fn preserve(link: &Path, options: &Options) -> Result<()> {
+    if !options.allow_foreign_dirs || unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+
+    let Some(directory) = link.parent() else {
+        return Ok(());
+    };
+
+    let metadata = directory
+        .metadata()
+        .with_context(|| format!("Unable to read the owner of {}", directory.display()))?;
+
+    std::os::unix::fs::lchown(link, Some(metadata.uid()), Some(metadata.gid()))
+        .with_context(|| format!("Unable to change the owner of {}", link.display()))
+}
+
+/// Returns the name of the user `uid`, or `uid` itself if it has none.
+fn user_name(uid: u32) -> String {
+    match with_passwd(uid, |passwd| {
+        unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned()
+    }) {
+        Some(name) => format!("{} (uid {uid})", name.to_string_lossy()),
+        None => format!("uid {uid}"),
+    }
+}
+
+/// Returns the home directory of the current user in the user database, for when `$HOME` isn't
+/// set (e.g. under some cron implementations).
+pub fn home_dir() -> Option<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+
+    let home = with_passwd(uid, |passwd| {
+        unsafe { CStr::from_ptr(passwd.pw_dir) }.to_owned()
+    })?;
+
+    (!home.is_empty()).then(|| PathBuf::from(OsStr::from_bytes(home.to_bytes())))
+}
+
+/// Calls `f` with the entry of the user `uid` in the user database, if it has one. Its strings
+/// only live for the duration of the call.
+fn with_passwd<T>(uid: u32, f: impl FnOnce(&libc::passwd) -> T) -> Option<T> {
+    let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+    let mut buffer = [0; 4096];
+    let mut result = std::ptr::null_mut();
+
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if status != 0 || result.is_null() {
+        return None;
+    }
+
+    // `result` points to `passwd`, whose strings point into `buffer`.
+    Some(f(unsafe { &*result }))
+}
@@ -0,0 +1,170 @@
+//! Missing parent directories of targets, e.g. `~/.config` on a fresh machine, are created when
+//! linking and recorded in `$XDG_STATE_HOME/xdot/parents.json`, so that unlinking removes those
+//! which end up empty.
+
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{overlay::Overlay, ownership, shell_quote, term, transient, Context, Options};
+
+/// Returns where created directories are recorded, unless `XDG_STATE_HOME` can't be resolved.
+pub fn record(context: &Context) -> Option<PathBuf> {
+    context
+        .resolve_var("XDG_STATE_HOME".as_ref())
+        .map(|state| state.join("xdot/parents.json"))
+}
+
+fn load(path: &Path) -> Result<BTreeSet<PathBuf>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(error) => Err(error).with_context(|| format!("Unable to read {}", path.display())),
+    }
+}
+
+fn save(path: &Path, directories: &BTreeSet<PathBuf>) -> Result<()> {
+    if directories.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                Err(error).with_context(|| format!("Unable to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+
+    std::fs::write(path, serde_json::to_string_pretty(directories)? + "\n")
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Creates the missing ancestors of `link`, outermost first. With `--dry-run`, they are recorded
+/// into `overlay` instead.
+pub fn create(link: &Path, options: &Options, overlay: &mut Overlay) -> Result<()> {
+    let missing = link
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| !overlay.get(ancestor).exists_no_follow())
+        .map(Path::to_owned)
+        .collect::<Vec<_>>();
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    for directory in missing.iter().rev() {
+        if options.output_sh {
+            println!("mkdir {}", shell_quote(directory.as_os_str()));
+        } else {
+            println!("Creating directory: {}", directory.display());
+        }
+
+        if options.dry_run {
+            overlay.create_dir(directory);
+        } else {
+            transient::create_dir(directory, options)
+                .with_context(|| format!("Unable to create {}", directory.display()))?;
+
+            ownership::preserve(directory, options)?;
+        }
+    }
+
+    let Some(path) = options.parents_record.as_ref().filter(|_| !options.dry_run) else {
+        return Ok(());
+    };
+
+    let mut directories = load(path)?;
+
+    directories.extend(missing);
+
+    save(path, &directories)
+}
+
+/// Removes the recorded directories which are empty once links are removed, innermost first. Only
+/// those leading to, or found below, the package entries linked at `links` are considered, others
+/// were created for packages which are still linked.
+pub fn remove_empty(links: &[PathBuf], options: &Options) -> Result<()> {
+    // Whether they would end up empty isn't known beforehand.
+    let Some(path) = options.parents_record.as_ref().filter(|_| !options.dry_run) else {
+        return Ok(());
+    };
+
+    let mut directories = load(path)?;
+
+    // Directories sort before their content.
+    for directory in directories.clone().iter().rev() {
+        if !links
+            .iter()
+            .any(|link| link.starts_with(directory) || directory.starts_with(link))
+        {
+            continue;
+        }
+
+        match std::fs::remove_dir(directory) {
+            Ok(()) => {
+                println!(
+                    "{}",
+                    term::paint(
+                        &format!("Removing directory: {}", directory.display()),
+                        term::Color::Yellow
+                    )
+                );
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                ) => {}
+            // Left for a later run, once emptied.
+            Err(error) if error.kind() == io::ErrorKind::DirectoryNotEmpty => continue,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Unable to remove {}", directory.display()))
+            }
+        }
+
+        directories.remove(directory);
+    }
+
+    save(path, &directories)
+}
+
+/// Forgets the recorded directories which no longer exist, e.g. removed by hand, for `xdot
+/// backups --prune`.
+pub fn vacuum(options: &Options) -> Result<()> {
+    let Some(path) = options.parents_record.as_ref() else {
+        return Ok(());
+    };
+
+    let mut directories = load(path)?;
+    let count = directories.len();
+
+    directories.retain(|directory| directory.is_dir());
+
+    if directories.len() == count {
+        return Ok(());
+    }
+
+    if options.verbosity > 0 {
+        println!(
+            "Forgetting {} created directory(ies) which no longer exist",
+            count - directories.len()
+        );
+    }
+
+    if options.dry_run {
+        return Ok(());
+    }
+
+    save(path, &directories)
+}
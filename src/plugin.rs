@@ -0,0 +1,37 @@
+//! Git-style external commands: `xdot foo` runs `xdot-foo` from `$PATH`.
+
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{executables::is_executable, Context};
+
+/// Returns the path of the `xdot-<name>` executable in `$PATH`, if any.
+pub fn find(name: &OsStr) -> Option<PathBuf> {
+    if name.is_empty() || name.as_bytes().starts_with(b"-") || name.as_bytes().contains(&b'/') {
+        return None;
+    }
+
+    let mut file_name = OsString::from("xdot-");
+    file_name.push(name);
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+/// Replaces the current process with `program`, exposing xdot's context through the environment.
+pub fn exec(context: &Context, program: &Path, args: &[OsString]) -> Result<()> {
+    let error = std::process::Command::new(program)
+        .args(args)
+        .env("XDOT_PACKAGES_ROOT", &*context.packages_root)
+        .env("XDOT_TARGET_ROOT", context.layout_root())
+        .env("XDOT_CONFIG", &*context.config_path)
+        .exec();
+
+    Err(error).with_context(|| format!("Unable to execute {}", program.display()))
+}
@@ -0,0 +1,210 @@
+//! `xdot prune`: remove the symlinks into packages which broke because their package file was
+//! deleted (or moved).
+
+use std::{
+    collections::BTreeSet,
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    dot_prefix, is_excluded, is_selected, is_vcs_dir, link, print_removal, Context, Options,
+    PackageSpec,
+};
+
+/// A symlink into the packages root whose target doesn't exist.
+pub struct BrokenLink {
+    pub link: PathBuf,
+    pub target: PathBuf,
+    /// The package `target` belongs to, which may no longer exist either.
+    pub package: OsString,
+}
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let pruned = match package_spec {
+        PackageSpec::List(packages) => prune(context, Some(&packages), options)?,
+        PackageSpec::None | PackageSpec::All => prune(context, None, options)?,
+    };
+
+    if pruned == 0 && !options.output_sh {
+        println!("Nothing to prune.");
+    }
+
+    Ok(())
+}
+
+/// `xdot restow`: removes the broken links into packages, e.g. left behind by files moved around
+/// within them, then links them again.
+pub fn restow(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let packages = context.packages(package_spec)?;
+
+    prune(context, Some(&packages), options)?;
+
+    link(context, PackageSpec::List(packages.into_vec()), options)
+}
+
+/// Removes the broken links into `packages` (or into any package), returns how many there were.
+fn prune(context: &Context, packages: Option<&[Box<OsStr>]>, options: &Options) -> Result<usize> {
+    let mut pruned = 0;
+
+    for broken in broken_links(context, options)? {
+        let selected = packages
+            .is_none_or(|packages| packages.iter().any(|package| **package == *broken.package));
+
+        if !selected || !is_selected("unlink", &broken.target, &broken.link, options) {
+            continue;
+        }
+
+        print_removal(&broken.link, options);
+
+        if !options.dry_run {
+            std::fs::remove_file(&broken.link)
+                .with_context(|| format!("Unable to remove {}", broken.link.display()))?;
+        }
+
+        pruned += 1;
+    }
+
+    Ok(pruned)
+}
+
+/// Finds the symlinks into the packages root whose target doesn't exist, in the directories the
+/// packages link into: those of their package entries and of the directories below them. Links
+/// into deleted packages, or deleted directories of a package, are found when they sit next to
+/// the links of remaining ones.
+pub fn broken_links(context: &Context, options: &Options) -> Result<Vec<BrokenLink>> {
+    // The packages root itself may be a symlink.
+    let roots = [
+        Some(context.packages_root.to_path_buf()),
+        context.packages_root.canonicalize().ok(),
+    ];
+
+    let mut directories = BTreeSet::new();
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        // Invalid packages are reported when linking them.
+        let Ok(entries) = context.package_entries(&context.package_path(package), options) else {
+            continue;
+        };
+
+        for (original, link) in entries {
+            if let Some(parent) = link.parent() {
+                directories.insert(parent.to_owned());
+            }
+
+            link_directories(context, &original, &link, options, &mut directories);
+        }
+    }
+
+    let mut broken = Vec::new();
+
+    for directory in directories {
+        // Folded into a package, or one of the links looked for.
+        if directory
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_symlink())
+        {
+            continue;
+        }
+
+        // Unreadable directories aren't worth reporting here.
+        let Ok(entries) = directory.read_dir() else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            if !entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_symlink())
+            {
+                continue;
+            }
+
+            let link = entry.path();
+
+            let Ok(target) = link.read_link() else {
+                continue;
+            };
+
+            let target = normalize(&directory.join(target));
+
+            let Some(package) = roots.iter().flatten().find_map(|root| {
+                let relative = target.strip_prefix(root).ok()?;
+
+                Some(relative.components().next()?.as_os_str().to_owned())
+            }) else {
+                continue;
+            };
+
+            if target.exists() {
+                continue;
+            }
+
+            broken.push(BrokenLink {
+                link,
+                target,
+                package,
+            });
+        }
+    }
+
+    Ok(broken)
+}
+
+/// Collects into `directories` the would-be links of `original` and of the directories below it,
+/// if it is a directory.
+fn link_directories(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    directories: &mut BTreeSet<PathBuf>,
+) {
+    if !original
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return;
+    }
+
+    directories.insert(link.to_owned());
+
+    let Ok(entries) = original.read_dir() else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if is_vcs_dir(&entry.file_name()) || is_excluded(context, &path, options) {
+            continue;
+        }
+
+        link_directories(
+            context,
+            &path,
+            &link.join(dot_prefix::link_name(&path, options)),
+            options,
+            directories,
+        );
+    }
+}
+
+/// Resolves `.` and `..` components of `path` without querying the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
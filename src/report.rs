@@ -0,0 +1,150 @@
+//! `xdot report`: write a standalone HTML page summarizing the link status of every package.
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use anyhow::{Context as _, Result};
+
+use crate::{walk_files, Context, LinkState, Options, PackageSpec};
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;margin-bottom:2em}\
+td,th{border:1px solid #ccc;padding:.2em .6em;text-align:left;font-family:monospace}\
+.linked,.copied{color:green}.missing{color:gray}\
+.wrong-target,.conflict,.copy-modified{color:red;font-weight:bold}";
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    let mut packages = Vec::<(String, Vec<(PathBuf, PathBuf, LinkState)>)>::new();
+
+    for package in context.packages(package_spec)?.iter() {
+        let mut files = Vec::new();
+
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                files.push((
+                    original.to_owned(),
+                    link.to_owned(),
+                    LinkState::of(original, link, options),
+                ));
+
+                Ok(())
+            })?;
+        }
+
+        packages.push((package.to_string_lossy().into_owned(), files));
+    }
+
+    let mut html = String::new();
+
+    writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>xdot report</title>\n<style>{STYLE}</style>\n</head>\n<body>"
+    )?;
+    writeln!(html, "<h1>xdot report</h1>")?;
+    writeln!(
+        html,
+        "<p>xdot {}, packages root: <code>{}</code></p>",
+        env!("CARGO_PKG_VERSION"),
+        escape(&context.packages_root.to_string_lossy())
+    )?;
+
+    writeln!(html, "<h2>Summary</h2>\n<table>")?;
+    writeln!(
+        html,
+        "<tr><th>Package</th><th>Linked</th><th>Copied</th><th>Missing</th><th>Wrong target</th><th>Conflict</th><th>Copy modified</th></tr>"
+    )?;
+
+    for (package, files) in &packages {
+        let count = |state| files.iter().filter(|(_, _, s)| *s == state).count();
+
+        writeln!(
+            html,
+            "<tr><td><a href=\"#{0}\">{0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td><td>{4}</td><td>{5}</td><td>{6}</td></tr>",
+            escape(package),
+            count(LinkState::Linked),
+            count(LinkState::Copied),
+            count(LinkState::Missing),
+            count(LinkState::WrongTarget),
+            count(LinkState::Conflict),
+            count(LinkState::CopyModified),
+        )?;
+    }
+
+    writeln!(html, "</table>")?;
+
+    writeln!(html, "<h2>Conflicts</h2>")?;
+
+    let conflicts = packages
+        .iter()
+        .flat_map(|(package, files)| files.iter().map(move |file| (package, file)))
+        .filter(|(_, (_, _, state))| {
+            matches!(
+                state,
+                LinkState::WrongTarget | LinkState::Conflict | LinkState::CopyModified
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if conflicts.is_empty() {
+        writeln!(html, "<p>None.</p>")?;
+    } else {
+        writeln!(
+            html,
+            "<table>\n<tr><th>Package</th><th>Target</th><th>State</th></tr>"
+        )?;
+
+        for (package, (_, link, state)) in conflicts {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td></tr>",
+                escape(package),
+                escape(&link.to_string_lossy()),
+                state.as_str().replace(' ', "-"),
+                state.as_str()
+            )?;
+        }
+
+        writeln!(html, "</table>")?;
+    }
+
+    for (package, files) in &packages {
+        writeln!(html, "<h2 id=\"{0}\">{0}</h2>\n<table>", escape(package))?;
+        writeln!(
+            html,
+            "<tr><th>Source</th><th>Target</th><th>State</th></tr>"
+        )?;
+
+        for (original, link, state) in files {
+            writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td></tr>",
+                escape(&original.to_string_lossy()),
+                escape(&link.to_string_lossy()),
+                state.as_str().replace(' ', "-"),
+                state.as_str()
+            )?;
+        }
+
+        writeln!(html, "</table>")?;
+    }
+
+    writeln!(html, "</body>\n</html>")?;
+
+    match &options.output {
+        Some(path) => {
+            std::fs::write(path, html)
+                .with_context(|| format!("Unable to write {}", path.display()))?;
+
+            println!("Report written to {}", path.display());
+        }
+        None => print!("{html}"),
+    }
+
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
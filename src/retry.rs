@@ -0,0 +1,220 @@
+//! `--keep-going` and `xdot retry`: record the operations which failed, to re-attempt only them
+//! once the cause is fixed.
+
+use std::{ffi::OsStr, path::PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    apply_entry, backup, overlay::Overlay, preflight, report_skipped, term, Context,
+    DanglingPolicy, LinkStrategy, Options, PackageSpec,
+};
+
+/// A top-level package entry which couldn't be linked (or unlinked).
+#[derive(Serialize, Deserialize)]
+pub struct Failure {
+    pub package: String,
+    pub original: PathBuf,
+    pub link: PathBuf,
+}
+
+/// The options of the failed run which change how entries are linked, reapplied when retrying.
+#[derive(Serialize, Deserialize)]
+struct RunOptions {
+    unlink: bool,
+    strategy: LinkStrategy,
+    force: bool,
+    backup: Option<PathBuf>,
+    no_folding: bool,
+    follow_dir_symlinks: bool,
+    keep_broken: bool,
+    include_vcs: bool,
+    allow_foreign_dirs: bool,
+    dangling_sources: DanglingPolicy,
+}
+
+impl RunOptions {
+    fn of(options: &Options) -> Self {
+        Self {
+            unlink: options.unlink,
+            strategy: options.strategy,
+            force: options.force,
+            backup: options.backup.clone(),
+            no_folding: options.no_folding,
+            follow_dir_symlinks: options.follow_dir_symlinks,
+            keep_broken: options.keep_broken,
+            include_vcs: options.include_vcs,
+            allow_foreign_dirs: options.allow_foreign_dirs,
+            dangling_sources: options.dangling_sources,
+        }
+    }
+
+    /// Sets the options of the failed run, `--allow-foreign-dirs` being kept when given again.
+    fn apply(self, options: &mut Options) {
+        options.strategy = self.strategy;
+        options.force = self.force;
+        options.backup = self.backup;
+        options.no_folding = self.no_folding;
+        options.follow_dir_symlinks = self.follow_dir_symlinks;
+        options.keep_broken = self.keep_broken;
+        options.include_vcs = self.include_vcs;
+        options.allow_foreign_dirs |= self.allow_foreign_dirs;
+        options.dangling_sources = self.dangling_sources;
+    }
+}
+
+/// The content of `failed.json`.
+#[derive(Serialize, Deserialize)]
+struct Failed {
+    options: RunOptions,
+    failures: Vec<Failure>,
+}
+
+/// Returns where failures are recorded, `$XDG_STATE_HOME/xdot/failed.json`.
+fn path(context: &Context) -> Result<PathBuf> {
+    let state = context
+        .resolve_var("XDG_STATE_HOME".as_ref())
+        .context("Unable to resolve `XDG_STATE_HOME`")?;
+
+    Ok(state.join("xdot/failed.json"))
+}
+
+/// Records `failures` along with the `options` of the run, replacing previous ones.
+pub fn save(context: &Context, failures: &[Failure], options: &Options) -> Result<()> {
+    let path = path(context)?;
+
+    if failures.is_empty() {
+        return match std::fs::remove_file(&path) {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(error).with_context(|| format!("Unable to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+
+    let failed = serde_json::json!({
+        "options": RunOptions::of(options),
+        "failures": failures,
+    });
+
+    std::fs::write(&path, serde_json::to_string_pretty(&failed)? + "\n")
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Reports a failed operation, to be recorded by [`save`].
+pub fn report(error: &anyhow::Error) {
+    eprintln!(
+        "{}",
+        term::paint(&format!("Error: {error:#}"), term::Color::Red)
+    );
+}
+
+/// Re-attempts the recorded failures with the options of the run which failed, once the
+/// packages they belong to pass the same checks as when linking them.
+pub fn run(context: &Context, options: &mut Options) -> Result<()> {
+    let path = path(context)?;
+
+    let Failed {
+        options: run_options,
+        failures,
+    } = match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            println!("Nothing to retry.");
+
+            return Ok(());
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("Unable to read {}", path.display()))
+        }
+    };
+
+    if failures.is_empty() {
+        println!("Nothing to retry.");
+
+        return Ok(());
+    }
+
+    if run_options.unlink != options.unlink {
+        bail!(
+            "The failed run {}, run `xdot retry{}`",
+            if options.unlink {
+                "linked packages"
+            } else {
+                "unlinked packages"
+            },
+            if options.unlink { "" } else { " --unlink" }
+        );
+    }
+
+    run_options.apply(options);
+
+    if options.backup.is_some() || options.force {
+        options.backup_session = Some(backup::session(
+            context,
+            options.backup.as_deref().unwrap_or("".as_ref()),
+        )?);
+    }
+
+    let mut packages: Vec<Box<OsStr>> = Vec::new();
+
+    for failure in &failures {
+        let package = OsStr::new(&failure.package);
+
+        if !packages.iter().any(|known| &**known == package) {
+            packages.push(package.into());
+        }
+    }
+
+    let packages = context.packages(PackageSpec::List(packages))?;
+
+    context.check_vars(&packages, options)?;
+    preflight(context, &packages, options)?;
+
+    let options = &*options;
+    let mut remaining = Vec::new();
+    let mut skipped = Vec::new();
+    let mut overlay = Overlay::default();
+
+    for failure in failures {
+        println!(
+            "Retrying {} (`{}`)",
+            failure.link.display(),
+            failure.package
+        );
+
+        if let Err(error) = apply_entry(
+            context,
+            &failure.original,
+            &failure.link,
+            options,
+            &mut Vec::new(),
+            &mut skipped,
+            &mut overlay,
+        ) {
+            report(&error);
+
+            remaining.push(failure);
+        }
+    }
+
+    report_skipped(&skipped);
+
+    if !options.dry_run {
+        save(context, &remaining, options)?;
+    }
+
+    if !remaining.is_empty() {
+        bail!("{} operation(s) failed again", remaining.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+//! `xdot scan`: look for well-known dotfiles and offer to adopt them into packages.
+
+use std::{
+    ffi::OsStr,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{backup, prompt, Context, Options};
+
+/// A well-known dotfile, relative to the directory designated by `var`.
+struct Entry {
+    package: &'static str,
+    var: &'static str,
+    path: &'static str,
+}
+
+macro_rules! catalog {
+    ($($package:literal => $var:literal / $path:literal),* $(,)?) => {
+        &[$(Entry { package: $package, var: $var, path: $path }),*]
+    };
+}
+
+const CATALOG: &[Entry] = catalog![
+    "alacritty" => "XDG_CONFIG_HOME" / "alacritty",
+    "bash" => "HOME" / ".bash_profile",
+    "bash" => "HOME" / ".bashrc",
+    "fish" => "XDG_CONFIG_HOME" / "fish",
+    "git" => "HOME" / ".gitconfig",
+    "git" => "XDG_CONFIG_HOME" / "git",
+    "helix" => "XDG_CONFIG_HOME" / "helix",
+    "kitty" => "XDG_CONFIG_HOME" / "kitty",
+    "nvim" => "XDG_CONFIG_HOME" / "nvim",
+    "starship" => "XDG_CONFIG_HOME" / "starship.toml",
+    "tmux" => "HOME" / ".tmux.conf",
+    "tmux" => "XDG_CONFIG_HOME" / "tmux",
+    "vim" => "HOME" / ".vimrc",
+    "zsh" => "HOME" / ".zprofile",
+    "zsh" => "HOME" / ".zshenv",
+    "zsh" => "HOME" / ".zshrc",
+];
+
+pub fn run(context: &Context, options: &Options) -> Result<()> {
+    let mut found = false;
+
+    for entry in CATALOG {
+        let Some(base) = context.resolve_var(OsStr::new(entry.var)) else {
+            continue;
+        };
+
+        let target = base.join(entry.path);
+
+        let Ok(metadata) = target.symlink_metadata() else {
+            continue;
+        };
+
+        if metadata.is_symlink() {
+            if options.verbosity > 0 {
+                println!("Skipping symlink: {}", target.display());
+            }
+
+            continue;
+        }
+
+        found = true;
+
+        let destination = PathBuf::from_iter([
+            &*context.packages_root,
+            Path::new(entry.package),
+            Path::new(&format!("@{}", entry.var)),
+            Path::new(entry.path),
+        ]);
+
+        if destination.symlink_metadata().is_ok() {
+            println!(
+                "Skipping {}: {} already exists",
+                target.display(),
+                destination.display()
+            );
+
+            continue;
+        }
+
+        if prompt(
+            &format!(
+                "Adopt {} into `{}` as @{}/{}?",
+                target.display(),
+                entry.package,
+                entry.var,
+                entry.path
+            ),
+            options,
+        )? {
+            adopt(&target, &destination, options)?;
+        }
+    }
+
+    if !found {
+        println!("No well-known dotfiles found.");
+    }
+
+    Ok(())
+}
+
+/// Moves `target` to `destination` then symlinks `destination` to `target`.
+pub fn adopt(target: &Path, destination: &Path, options: &Options) -> Result<()> {
+    println!("Moving {} to {}", target.display(), destination.display());
+
+    if !options.dry_run {
+        backup::move_file(target, destination)?;
+    }
+
+    println!("{} => {}", target.display(), destination.display());
+
+    if !options.dry_run {
+        symlink(destination, target).with_context(|| {
+            format!(
+                "Unable to symlink {} => {}",
+                target.display(),
+                destination.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
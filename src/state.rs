@@ -0,0 +1,265 @@
+//! `xdot state export` and `xdot state diff FILE`: record which package files a machine links and
+//! in what state, then compare it with the record of another machine, to find out why a
+//! configuration works on one and not the other.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::CStr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{status::tilde, walk_files, Context, LinkState, Options, PackageSpec};
+
+pub enum Action {
+    Export,
+    Diff(Option<PathBuf>),
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action {
+            "export" => Ok(Self::Export),
+            "diff" => Ok(Self::Diff(None)),
+            _ => Err(anyhow!(
+                "Unknown action `{action}`, expected `export` or `diff`"
+            )),
+        }
+    }
+}
+
+/// Bumped on incompatible changes to the state, fields may be added without bumping it.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct State {
+    schema_version: u32,
+    host: String,
+    /// The commit the packages repository is at, if it is a git repository.
+    commit: Option<String>,
+    /// The files of every package by target, with `~` standing for the home directory.
+    packages: BTreeMap<String, BTreeMap<PathBuf, File>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+struct File {
+    /// See [`LinkState::as_str`].
+    state: String,
+    /// The FNV-1a hash of the package file, which differs between machines at different commits
+    /// or with local changes.
+    content: String,
+}
+
+pub fn run(context: &Context, action: Action, options: &Options) -> Result<()> {
+    match action {
+        Action::Export => export(context, options),
+        Action::Diff(None) => bail!("Missing state file, see `xdot state export`"),
+        Action::Diff(Some(path)) => diff(context, &path, options),
+    }
+}
+
+fn export(context: &Context, options: &Options) -> Result<()> {
+    let output = serde_json::to_string_pretty(&State::of(context, options)?)? + "\n";
+
+    match &options.output {
+        Some(path) => {
+            std::fs::write(path, output)
+                .with_context(|| format!("Unable to write {}", path.display()))?;
+
+            println!("Exported to {}", path.display());
+        }
+        None => print!("{output}"),
+    }
+
+    Ok(())
+}
+
+fn diff(context: &Context, path: &Path, options: &Options) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+
+    let other: serde_json::Value =
+        serde_json::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?;
+
+    // Fields added since are ignored, anything else can't be compared reliably.
+    match other
+        .get("schema_version")
+        .and_then(serde_json::Value::as_u64)
+    {
+        Some(version) if version == u64::from(SCHEMA_VERSION) => {}
+        Some(version) => bail!(
+            "{} has schema version {version}, expected {SCHEMA_VERSION}, export it again with \
+            this version of xdot",
+            path.display()
+        ),
+        None => bail!(
+            "{} has no schema version, export it again with this version of xdot",
+            path.display()
+        ),
+    }
+
+    let other: State =
+        serde_json::from_value(other).with_context(|| format!("Invalid {}", path.display()))?;
+
+    let here = State::of(context, options)?;
+    let there = format!("on {}", other.host);
+
+    println!("Comparing with {} ({})", other.host, path.display());
+
+    let mut differences = 0;
+
+    if here.commit != other.commit {
+        differences += 1;
+
+        println!(
+            "Commit: {} here, {} {there}",
+            here.commit.as_deref().unwrap_or("none"),
+            other.commit.as_deref().unwrap_or("none")
+        );
+    }
+
+    let names = here.packages.keys().chain(other.packages.keys());
+
+    for name in names.collect::<BTreeSet<_>>() {
+        let (files, other_files) = match (here.packages.get(name), other.packages.get(name)) {
+            (Some(files), Some(other_files)) => (files, other_files),
+            (files, _) => {
+                differences += 1;
+
+                println!(
+                    "Package `{name}`: only {}",
+                    if files.is_some() { "here" } else { &there }
+                );
+
+                continue;
+            }
+        };
+
+        let mut lines = Vec::new();
+
+        for link in files
+            .keys()
+            .chain(other_files.keys())
+            .collect::<BTreeSet<_>>()
+        {
+            let line = match (files.get(link), other_files.get(link)) {
+                (Some(file), Some(other_file)) if file == other_file => continue,
+                (Some(file), Some(other_file)) if file.state != other_file.state => {
+                    format!("{} here, {} {there}", file.state, other_file.state)
+                }
+                (Some(_), Some(_)) => String::from("content differs"),
+                (Some(_), None) => String::from("only here"),
+                (None, _) => format!("only {there}"),
+            };
+
+            lines.push((link, line));
+        }
+
+        if lines.is_empty() {
+            continue;
+        }
+
+        differences += lines.len();
+
+        println!("{name}");
+
+        for (link, line) in lines {
+            println!("  {}: {line}", link.display());
+        }
+    }
+
+    if differences > 0 {
+        bail!("{differences} difference(s) with {}", other.host);
+    }
+
+    println!("No differences.");
+
+    Ok(())
+}
+
+impl State {
+    fn of(context: &Context, options: &Options) -> Result<Self> {
+        let mut packages = BTreeMap::new();
+
+        for package in context.packages(PackageSpec::All)?.iter() {
+            let mut files = BTreeMap::new();
+
+            for (original, link) in
+                context.package_entries(&context.package_path(package), options)?
+            {
+                walk_files(context, &original, &link, options, &mut |original, link| {
+                    files.insert(
+                        tilde(context, link),
+                        File {
+                            state: LinkState::of(original, link, options).as_str().to_owned(),
+                            content: hash(original),
+                        },
+                    );
+
+                    Ok(())
+                })?;
+            }
+
+            packages.insert(package.to_string_lossy().into_owned(), files);
+        }
+
+        Ok(Self {
+            schema_version: SCHEMA_VERSION,
+            host: host_name(),
+            commit: commit(context),
+            packages,
+        })
+    }
+}
+
+/// Returns the FNV-1a hash of the file at `path`, stable across versions and machines unlike
+/// [`std::hash::DefaultHasher`].
+fn hash(path: &Path) -> String {
+    let Ok(content) = std::fs::read(path) else {
+        return String::from("unreadable");
+    };
+
+    let hash = content
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+
+    format!("{hash:016x}")
+}
+
+/// Returns the name of this machine, as printed by `hostname`.
+pub fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+
+    // SAFETY: `buffer` is writable for its whole length, the last byte is kept for the nul
+    // terminator in case the name is truncated.
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len() - 1) } != 0 {
+        return String::from("unknown");
+    }
+
+    CStr::from_bytes_until_nul(&buffer).map_or_else(
+        |_| String::from("unknown"),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// Returns the commit the packages repository is at, if it is a git repository.
+fn commit(context: &Context) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(&*context.packages_root)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
@@ -0,0 +1,89 @@
+//! `xdot status`: summarize the link state of package files, grouped by target directory.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{modes, walk_files, Context, LinkState, Options, PackageSpec};
+
+const STATES: [LinkState; 6] = [
+    LinkState::Linked,
+    LinkState::Copied,
+    LinkState::Missing,
+    LinkState::WrongTarget,
+    LinkState::Conflict,
+    LinkState::CopyModified,
+];
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    for package in context.packages(package_spec)?.iter() {
+        let mut directories = BTreeMap::<PathBuf, Vec<(PathBuf, LinkState)>>::new();
+        let mut drifted = Vec::new();
+
+        for (original, link) in context.package_entries(&context.package_path(package), options)? {
+            drifted.extend(modes::drifted(context, &original, options));
+
+            walk_files(context, &original, &link, options, &mut |original, link| {
+                directories
+                    .entry(link.parent().unwrap_or(link).to_owned())
+                    .or_default()
+                    .push((link.to_owned(), LinkState::of(original, link, options)));
+
+                Ok(())
+            })?;
+        }
+
+        println!("{}", package.to_string_lossy());
+
+        for (directory, files) in &directories {
+            let counts = STATES
+                .iter()
+                .filter_map(|&state| {
+                    let count = files.iter().filter(|(_, s)| *s == state).count();
+
+                    (count > 0).then(|| format!("{count} {}", state.as_str()))
+                })
+                .collect::<Vec<_>>();
+
+            println!(
+                "  {}: {}",
+                tilde(context, directory).display(),
+                counts.join(", ")
+            );
+
+            if options.verbosity > 0 {
+                for (link, state) in files {
+                    println!(
+                        "    {}: {}",
+                        link.file_name().unwrap_or_default().to_string_lossy(),
+                        state.as_str()
+                    );
+                }
+            }
+        }
+
+        for drift in &drifted {
+            println!(
+                "  {}: mode {:03o}, {:03o} in {}",
+                tilde(context, &drift.path).display(),
+                drift.mode,
+                drift.expected,
+                modes::FILE
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Abbreviates `$HOME` to `~`.
+pub fn tilde(context: &Context, path: &Path) -> PathBuf {
+    match path.strip_prefix(&context.home) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("~"),
+        Ok(relative) => Path::new("~").join(relative),
+        Err(_) => path.to_owned(),
+    }
+}
@@ -0,0 +1,81 @@
+//! `--only GLOB` and `--exclude GLOB`: link a subset of the package files in a single run, without
+//! editing `.xdotignore`. Globs have the syntax of `.gitignore` files, relative to each package.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::Options;
+
+pub struct Subset {
+    packages_root: PathBuf,
+    /// Empty when every package file is included.
+    only: Gitignore,
+    exclude: Gitignore,
+}
+
+/// Restricts the package files under `packages_root` to those matching `only` (if any) and none
+/// of `exclude`, unless no glob is given.
+pub fn build(packages_root: &Path, only: &[String], exclude: &[String]) -> Result<Option<Subset>> {
+    if only.is_empty() && exclude.is_empty() {
+        return Ok(None);
+    }
+
+    let build = |option: &str, globs: &[String]| {
+        // Package files are matched relative to their package.
+        let mut builder = GitignoreBuilder::new("");
+
+        for glob in globs {
+            builder
+                .add_line(None, glob)
+                .with_context(|| format!("Invalid glob `{glob}` given to `--{option}`"))?;
+        }
+
+        builder
+            .build()
+            .with_context(|| format!("Invalid `--{option}` globs"))
+    };
+
+    Ok(Some(Subset {
+        packages_root: packages_root.to_owned(),
+        only: build("only", only)?,
+        exclude: build("exclude", exclude)?,
+    }))
+}
+
+/// Returns whether the package file (or directory) `original` is left out by the globs.
+/// Directories are only left out by `--exclude`, `--only` may match files below them.
+pub fn is_left_out(original: &Path, options: &Options) -> bool {
+    let Some(subset) = &options.subset else {
+        return false;
+    };
+
+    let Ok(relative) = original.strip_prefix(&subset.packages_root) else {
+        return false;
+    };
+
+    // Skips the package itself.
+    let relative = relative.components().skip(1).collect::<PathBuf>();
+
+    if relative.as_os_str().is_empty() {
+        return false;
+    }
+
+    let is_dir = original.is_dir();
+
+    if subset
+        .exclude
+        .matched_path_or_any_parents(&relative, is_dir)
+        .is_ignore()
+    {
+        return true;
+    }
+
+    !is_dir
+        && !subset.only.is_empty()
+        && !subset
+            .only
+            .matched_path_or_any_parents(&relative, false)
+            .is_ignore()
+}
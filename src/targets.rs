@@ -0,0 +1,81 @@
+//! `xdot targets`: list the top-level directories each package links into.
+
+use std::{collections::BTreeSet, path::Path};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    entry_link_name, is_excluded, is_package_metadata, is_topic_dir, strip_at_sign_prefix, Context,
+    Options, PackageSpec,
+};
+
+pub fn run(context: &Context, package_spec: PackageSpec, options: &Options) -> Result<()> {
+    for package in context.packages(package_spec)?.iter() {
+        let package_path = context.package_path(package);
+
+        let mut targets = BTreeSet::new();
+
+        collect_targets(
+            context,
+            &package_path,
+            &context.layout_root(),
+            true,
+            options,
+            &mut targets,
+        )?;
+
+        println!("{}", package.to_string_lossy());
+
+        for target in targets {
+            println!("  {target}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the targets of `directory`, the package itself (`is_package`) or a directory
+/// organizing it by topic, which is linked at `link`, see [`Context::collect_entries`].
+fn collect_targets(
+    context: &Context,
+    directory: &Path,
+    link: &Path,
+    is_package: bool,
+    options: &Options,
+    targets: &mut BTreeSet<String>,
+) -> Result<()> {
+    for entry in directory
+        .read_dir()
+        .with_context(|| format!("Unable to read package content ({})", directory.display()))?
+    {
+        let entry = entry?;
+        let file_name = entry.file_name();
+
+        if (is_package && is_package_metadata(&file_name))
+            || is_excluded(context, &entry.path(), options)
+        {
+            continue;
+        }
+
+        if let Some(name) = strip_at_sign_prefix(&file_name) {
+            targets.insert(match context.resolve_var(name) {
+                Some(directory) => {
+                    format!("{} (@{})", directory.display(), name.to_string_lossy())
+                }
+                None => format!("unresolved (@{})", name.to_string_lossy()),
+            });
+
+            continue;
+        }
+
+        let link = link.join(entry_link_name(&entry.path(), options));
+
+        if !is_topic_dir(context, &entry.path(), options) {
+            targets.insert(link.display().to_string());
+        } else {
+            collect_targets(context, &entry.path(), &link, false, options, targets)?;
+        }
+    }
+
+    Ok(())
+}
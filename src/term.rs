@@ -0,0 +1,90 @@
+//! Terminal output: colors, following the `NO_COLOR` and `CLICOLOR` conventions, and paths
+//! shortened to the terminal width.
+
+use std::{io::IsTerminal, path::Path, sync::OnceLock};
+
+#[derive(Clone, Copy)]
+pub enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+/// Whether to color the output, see <https://no-color.org> and <https://bixense.com/clicolors>.
+fn colors_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+
+    *ENABLED.get_or_init(|| {
+        let set = |name| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+
+        if set("NO_COLOR") {
+            false
+        } else if std::env::var_os("CLICOLOR_FORCE").is_some_and(|value| value != "0") {
+            true
+        } else if std::env::var_os("CLICOLOR").is_some_and(|value| value == "0") {
+            false
+        } else {
+            std::io::stdout().is_terminal()
+        }
+    })
+}
+
+pub fn paint(text: &str, color: Color) -> String {
+    if colors_enabled() {
+        format!("\x1b[{}m{text}\x1b[0m", color as u8)
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Returns the width of the terminal, if stdout is one.
+fn width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+    {
+        return Some(columns);
+    }
+
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+
+    // SAFETY: `TIOCGWINSZ` only writes a `winsize` to the given pointer.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    (result == 0 && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+/// Formats `link => original`, shortening `original` from the left to fit the terminal unless
+/// `full` is set (e.g. with `-v`).
+pub fn link_line(link: &Path, original: &Path, full: bool) -> String {
+    let link = link.display().to_string();
+    let original = original.display().to_string();
+
+    let available = match width() {
+        Some(width) if !full => width.saturating_sub(link.chars().count() + 4),
+        _ => return format!("{link} => {original}"),
+    };
+
+    let length = original.chars().count();
+
+    // Shortening to a handful of characters wouldn't be readable anyway.
+    if length <= available || available < 16 {
+        return format!("{link} => {original}");
+    }
+
+    let tail = original
+        .chars()
+        .skip(length - (available - 1))
+        .collect::<String>();
+
+    format!("{link} => …{tail}")
+}
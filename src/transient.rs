@@ -0,0 +1,78 @@
+//! Transient errors of network filesystems (NFS, SSHFS): operations failing with `EIO` or `ESTALE`
+//! are retried with a growing delay, up to `--retries` times, and entries which keep failing are
+//! reported once the run is over rather than aborting it.
+
+use std::{io, path::Path, time::Duration};
+
+use crate::Options;
+
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+fn is_transient_io(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EIO | libc::ESTALE))
+}
+
+/// Returns whether `error` was caused by a transient error, even after retrying.
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(is_transient_io)
+}
+
+/// Runs the idempotent `operation`, again while it fails with a transient error.
+pub fn retry<T>(options: &Options, mut operation: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut delay = INITIAL_DELAY;
+
+    for _ in 0..options.retries.unwrap_or(DEFAULT_RETRIES) {
+        match operation() {
+            Err(error) if is_transient_io(&error) => {
+                if options.verbosity > 0 {
+                    eprintln!("Warning: {error}, retrying in {}ms", delay.as_millis());
+                }
+
+                std::thread::sleep(delay);
+
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    operation()
+}
+
+/// Runs `operation`, which creates `path`, like [`retry`]: an attempt failing with a transient
+/// error may have created it nonetheless, which `is_created` tells.
+pub fn retry_creation(
+    path: &Path,
+    options: &Options,
+    mut operation: impl FnMut() -> io::Result<()>,
+    is_created: impl Fn(&Path) -> bool,
+) -> io::Result<()> {
+    let mut attempted = false;
+
+    retry(options, || {
+        let result = match operation() {
+            Err(error)
+                if attempted
+                    && error.kind() == io::ErrorKind::AlreadyExists
+                    && is_created(path) =>
+            {
+                Ok(())
+            }
+            result => result,
+        };
+
+        attempted = true;
+
+        result
+    })
+}
+
+/// Creates the directory at `path`, see [`retry_creation`].
+pub fn create_dir(path: &Path, options: &Options) -> io::Result<()> {
+    retry_creation(path, options, || std::fs::create_dir(path), Path::is_dir)
+}
@@ -0,0 +1,43 @@
+//! `--umask MODE`: the permissions of what a run creates don't depend on the umask of the calling
+//! shell, which differs e.g. under systemd timers.
+
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::Options;
+
+/// Parses an octal umask, e.g. `022`.
+pub fn parse(mode: &str) -> Result<u32> {
+    let umask = u32::from_str_radix(mode, 8)
+        .with_context(|| format!("Invalid umask `{mode}`, expected octal digits, e.g. `022`"))?;
+
+    if umask > 0o777 {
+        bail!("Invalid umask `{mode}`, expected at most `777`");
+    }
+
+    Ok(umask)
+}
+
+/// Sets the umask of the process for the rest of the run.
+pub fn set(umask: u32) {
+    // SAFETY: `umask` can't fail, the run is single-threaded at this point.
+    unsafe { libc::umask(umask as libc::mode_t) };
+}
+
+/// Applies `--umask` to the permissions copied from the package file `path` into a copy, as `cp`
+/// would: `std::fs::copy` keeps them as they are.
+pub fn restrict_copy(path: &Path, options: &Options) -> Result<()> {
+    let Some(umask) = options.umask else {
+        return Ok(());
+    };
+
+    let mode = path
+        .symlink_metadata()
+        .with_context(|| format!("Unable to read metadata of {}", path.display()))?
+        .permissions()
+        .mode();
+
+    std::fs::set_permissions(path, Permissions::from_mode(mode & !umask & 0o7777))
+        .with_context(|| format!("Unable to set permissions of {}", path.display()))
+}
@@ -0,0 +1,156 @@
+//! `xdot verify-repo`: lint the layout of the packages directory, without looking at `$HOME`, so
+//! that it can run in the CI of a dotfiles repository.
+
+use std::{io::Read, os::unix::fs::PermissionsExt, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+
+use crate::{
+    executables, is_excluded, is_package_metadata, strip_at_sign_prefix, var_dirs, walk_files, wsl,
+    Context, Options, PackageSpec, TERMUX_DEFAULT_VARS,
+};
+
+/// Files which may live at the root of the packages directory.
+const ROOT_FILES: &[&str] = &[
+    "hosts.toml",
+    "vars.toml",
+    "xdot.toml",
+    "README",
+    "README.md",
+    "LICENSE",
+];
+
+pub fn run(context: &Context, options: &Options) -> Result<()> {
+    let mut issues = Vec::<String>::new();
+
+    for entry in context
+        .packages_root
+        .read_dir()
+        .with_context(|| format!("Unable to read {}", context.packages_root.display()))?
+    {
+        let entry = entry?;
+        let name = entry.file_name();
+
+        if !entry.file_type()?.is_dir()
+            && !name.to_string_lossy().starts_with('.')
+            && !ROOT_FILES.iter().any(|file| name == *file)
+        {
+            issues.push(format!(
+                "{}: not in a package, it won't be linked",
+                entry.path().display()
+            ));
+        }
+    }
+
+    for package in context.packages(PackageSpec::All)?.iter() {
+        let package_path = context.package_path(package);
+
+        // Which package files are linked can't be told without them.
+        if let Err(error) = context.package_config(&package_path) {
+            issues.push(format!("{error:#}"));
+
+            continue;
+        }
+
+        let mut files = 0;
+
+        for entry in package_path.read_dir().with_context(|| {
+            format!(
+                "Unable to read package content ({})",
+                package_path.display()
+            )
+        })? {
+            let entry = entry?;
+
+            if is_package_metadata(&entry.file_name())
+                || is_excluded(context, &entry.path(), options)
+            {
+                continue;
+            }
+
+            walk_files(
+                context,
+                &entry.path(),
+                Path::new(""),
+                options,
+                &mut |original, _| {
+                    files += 1;
+
+                    if executables::is_meant_executable(original)
+                        && !executables::is_executable(original)
+                    {
+                        issues.push(format!(
+                            "{}: in `@XDG_BIN_HOME`, but not executable",
+                            original.display()
+                        ));
+                    }
+
+                    if is_suspiciously_executable(original)? {
+                        issues.push(format!(
+                            "{}: executable, but neither a script nor a binary",
+                            original.display()
+                        ));
+                    }
+
+                    Ok(())
+                },
+            )?;
+        }
+
+        for var_dir in var_dirs(context, &package_path, options) {
+            if let Some(name) = var_dir.file_name().and_then(strip_at_sign_prefix) {
+                if !is_known_var(context, name.to_str().unwrap_or_default()) {
+                    issues.push(format!(
+                        "{}: `{}` has no default, it must be set on every machine",
+                        var_dir.display(),
+                        name.to_string_lossy()
+                    ));
+                }
+            }
+        }
+
+        if files == 0 {
+            issues.push(format!("{}: empty package", package_path.display()));
+        }
+    }
+
+    for issue in &issues {
+        println!("{issue}");
+    }
+
+    if !issues.is_empty() {
+        bail!("{} issue(s) found", issues.len());
+    }
+
+    println!("No issues found.");
+
+    Ok(())
+}
+
+/// Returns whether a variable resolves without being set: `HOME`, the variables with a default
+/// (built in or from `vars.toml`), and the platform-specific `WINHOME` and Termux defaults, which
+/// only resolve under WSL and Termux, the platforms the packages using them are meant for.
+fn is_known_var(context: &Context, name: &str) -> bool {
+    name == "HOME"
+        || name == wsl::WINHOME
+        || TERMUX_DEFAULT_VARS.iter().any(|(var, _)| *var == name)
+        || context.default_var(name.as_ref()).is_some()
+}
+
+/// Returns whether `path` has an executable bit without starting with a shebang or an ELF header.
+fn is_suspiciously_executable(path: &Path) -> Result<bool> {
+    let metadata = path.symlink_metadata()?;
+
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Ok(false);
+    }
+
+    let mut header = Vec::with_capacity(4);
+
+    std::fs::File::open(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?
+        .take(4)
+        .read_to_end(&mut header)?;
+
+    Ok(!header.starts_with(b"#!") && !header.starts_with(b"\x7fELF"))
+}
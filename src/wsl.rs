@@ -0,0 +1,309 @@
+//! Windows Subsystem for Linux interop: `@WINHOME` designates the Windows user profile, files
+//! linked there are copied since symlinks created from WSL aren't reliable on NTFS.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use anyhow::{Context as _, Result};
+
+use crate::{
+    backup, diff, dot_prefix,
+    freeze::{copy_into_place, same_content},
+    is_excluded, is_selected, modes,
+    overlay::Overlay,
+    ownership, parents, report_conflict, resolve_conflict, selects_any, shell_quote, skip, term,
+    transient, Context, Options, SkipReason,
+};
+
+pub const WINHOME: &str = "WINHOME";
+
+fn is_wsl() -> bool {
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+}
+
+/// Returns the Windows user profile directory (e.g. `/mnt/c/Users/me`), when running under WSL.
+pub fn windows_home() -> Option<PathBuf> {
+    static HOME: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    HOME.get_or_init(|| {
+        if !is_wsl() {
+            return None;
+        }
+
+        let output = |command: &mut Command| {
+            command
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+                .filter(|output| !output.is_empty())
+        };
+
+        let profile = output(Command::new("cmd.exe").args(["/c", "echo %USERPROFILE%"]))?;
+
+        output(Command::new("wslpath").args(["-u", &profile])).map(PathBuf::from)
+    })
+    .clone()
+}
+
+/// Returns whether `original` is a top-level entry of a `@WINHOME` directory.
+pub fn is_windows_target(original: &Path) -> bool {
+    original
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|name| name == OsStr::new(&format!("@{WINHOME}")))
+}
+
+/// Returns whether the package file `original` is inside a `@WINHOME` directory, at any depth.
+pub fn is_windows_file(original: &Path) -> bool {
+    original
+        .ancestors()
+        .skip(1)
+        .any(|ancestor| ancestor.file_name() == Some(OsStr::new(&format!("@{WINHOME}"))))
+}
+
+/// Copies `original` to `link` (or, with `--unlink`, removes unmodified copies), descending into
+/// directories.
+///
+/// Copies which differ from `original` are conflicts, like files found where links go, unless
+/// `--force` is given: they are then backed up and updated. With `--dry-run`, changes are recorded into
+/// `overlay` instead of being made.
+pub fn copy_or_descend(
+    context: &Context,
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    conflicts: &mut Vec<(PathBuf, PathBuf)>,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    let planned = overlay.get(link);
+
+    if original.is_dir() {
+        if !planned.exists() && !options.unlink {
+            if !selects_any(context, original, link, options) {
+                skip(link, SkipReason::Filtered, options);
+
+                return Ok(());
+            }
+
+            parents::create(link, options, overlay)?;
+
+            if options.output_sh {
+                println!("mkdir {}", shell_quote(link.as_os_str()));
+            } else {
+                println!("Creating directory: {}", link.display());
+            }
+
+            if options.dry_run {
+                overlay.create_dir(link);
+            } else {
+                transient::create_dir(link, options)
+                    .with_context(|| format!("Unable to create {}", link.display()))?;
+
+                ownership::preserve(link, options)?;
+                modes::ensure(context, original, link, options)?;
+            }
+        }
+
+        for entry in original
+            .read_dir()
+            .with_context(|| format!("Unable to descend into {}", original.display()))?
+        {
+            let entry = entry?;
+
+            if is_excluded(context, &entry.path(), options) {
+                continue;
+            }
+
+            copy_or_descend(
+                context,
+                &entry.path(),
+                &link.join(dot_prefix::link_name(&entry.path(), options)),
+                options,
+                conflicts,
+                overlay,
+            )?;
+        }
+
+        return Ok(());
+    }
+
+    if !planned.exists_no_follow() {
+        if options.unlink {
+            skip(link, SkipReason::Missing, options);
+
+            return Ok(());
+        }
+
+        if !is_selected("link", original, link, options) {
+            return Ok(());
+        }
+
+        parents::create(link, options, overlay)?;
+
+        return place_copy(original, link, options, overlay);
+    }
+
+    let up_to_date = match planned.file() {
+        Some(file) => same_content(original, file)?,
+        None => false,
+    };
+
+    if !up_to_date {
+        if options.unlink {
+            skip(link, SkipReason::Modified, options);
+
+            return Ok(());
+        }
+
+        let existing = planned.file();
+
+        // Whether the package file or the copy changed since it was copied can't be told, e.g.
+        // `git pull` sets the modification time of the files it updates.
+        if options.force && existing.is_some() {
+            return update_copy(original, link, options, overlay);
+        }
+
+        if options.interactive {
+            if resolve_conflict(original, link, existing, options)? {
+                place_copy(original, link, options, overlay)?;
+            }
+
+            return Ok(());
+        }
+
+        let identical = match existing {
+            Some(existing) if !options.output_sh => diff::show_conflict(original, existing),
+            _ => false,
+        };
+
+        return report_conflict(
+            original,
+            link,
+            format!(
+                "{} already exists{}",
+                link.display(),
+                if identical {
+                    " (with the same content)"
+                } else {
+                    ""
+                }
+            ),
+            options,
+            conflicts,
+        );
+    }
+
+    if !options.unlink {
+        skip(link, SkipReason::UpToDate, options);
+
+        return Ok(());
+    }
+
+    if !is_selected("unlink", original, link, options) {
+        return Ok(());
+    }
+
+    if !options.output_sh {
+        println!(
+            "{}",
+            term::paint(
+                &format!("Removing copy: {}", link.display()),
+                term::Color::Yellow
+            )
+        );
+    }
+
+    backup::remove(link, options)?;
+
+    if options.dry_run {
+        overlay.remove(link);
+    }
+
+    Ok(())
+}
+
+/// Copies `original` to `link`, where nothing exists (or the conflicting file was removed).
+fn place_copy(
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    if options.output_sh {
+        println!(
+            "cp -p {} {}",
+            shell_quote(original.as_os_str()),
+            shell_quote(link.as_os_str())
+        );
+    } else {
+        println!(
+            "{}",
+            term::paint(
+                &format!("Copying {} to {}", original.display(), link.display()),
+                term::Color::Green
+            )
+        );
+    }
+
+    if options.dry_run {
+        overlay.copy(original, link);
+    } else {
+        copy_into_place(original, link, options)?;
+        ownership::preserve(link, options)?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the copy `link` of `original` with `--force`, backing it up first. The new copy is
+/// renamed over the previous one, so that `link` is never missing nor partially written.
+fn update_copy(
+    original: &Path,
+    link: &Path,
+    options: &Options,
+    overlay: &mut Overlay,
+) -> Result<()> {
+    if !is_selected("link", original, link, options) {
+        return Ok(());
+    }
+
+    if !options.output_sh {
+        println!(
+            "{}",
+            term::paint(
+                &format!(
+                    "Updating {} from {} (--force)",
+                    link.display(),
+                    original.display()
+                ),
+                term::Color::Green
+            )
+        );
+    }
+
+    // Edits made to the copy are never lost.
+    backup::copy(link, options)?;
+
+    if options.output_sh {
+        println!(
+            "cp -p {} {}",
+            shell_quote(original.as_os_str()),
+            shell_quote(link.as_os_str())
+        );
+    }
+
+    if options.dry_run {
+        overlay.copy(original, link);
+    } else {
+        copy_into_place(original, link, options)?;
+        ownership::preserve(link, options)?;
+    }
+
+    Ok(())
+}
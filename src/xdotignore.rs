@@ -0,0 +1,81 @@
+//! `.xdotignore`: package files matching its patterns (with the syntax of `.gitignore` files,
+//! relative to the package) aren't linked, e.g. a package's `LICENSE` or `screenshots/`. At the
+//! root of the packages directory, it leaves packages out of `--all`.
+//!
+//! The `ignore` patterns of `config.toml` apply to every package, before its `.xdotignore`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::Options;
+
+pub const FILE: &str = ".xdotignore";
+
+/// Reads the `.xdotignore` of every package under `packages_root`, after the `global` patterns of
+/// `config_path`. Returns the packages with patterns.
+pub fn load(
+    packages_root: &Path,
+    global: &[String],
+    config_path: &Path,
+) -> Result<Vec<(PathBuf, Gitignore)>> {
+    let mut packages = Vec::new();
+
+    // A missing packages root is reported when looking for packages.
+    if let Ok(entries) = packages_root.read_dir() {
+        for entry in entries.flatten() {
+            let package_path = entry.path();
+            let path = package_path.join(FILE);
+
+            if !package_path.is_dir() || (global.is_empty() && !path.is_file()) {
+                continue;
+            }
+
+            let mut builder = GitignoreBuilder::new(&package_path);
+
+            for pattern in global {
+                builder
+                    .add_line(Some(config_path.to_owned()), pattern)
+                    .with_context(|| format!("Invalid pattern `{pattern}` in config.toml"))?;
+            }
+
+            if path.is_file() {
+                if let Some(error) = builder.add(&path) {
+                    return Err(error).with_context(|| format!("Invalid {}", path.display()));
+                }
+            }
+
+            let patterns = builder
+                .build()
+                .with_context(|| format!("Invalid {}", path.display()))?;
+
+            packages.push((package_path, patterns));
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Returns the patterns of the package containing the package file `original`, if it has any.
+fn patterns<'a>(original: &Path, options: &'a Options) -> Option<&'a Gitignore> {
+    options
+        .ignored
+        .iter()
+        .find_map(|(package_path, patterns)| original.starts_with(package_path).then_some(patterns))
+}
+
+/// Returns whether the package file (or directory) `original` matches the `.xdotignore` of its
+/// package.
+pub fn is_ignored(original: &Path, options: &Options) -> bool {
+    patterns(original, options).is_some_and(|patterns| {
+        patterns
+            .matched_path_or_any_parents(original, original.is_dir())
+            .is_ignore()
+    })
+}
+
+/// Returns whether package files in the package directory `original` may be ignored.
+pub fn ignores_below(original: &Path, options: &Options) -> bool {
+    patterns(original, options).is_some()
+}